@@ -0,0 +1,3 @@
+alter table secrets add column path text unique;
+alter table secrets add constraint secrets_path_format
+  check (path ~ '^[A-Za-z0-9_.-]+(/[A-Za-z0-9_.-]+)*$');
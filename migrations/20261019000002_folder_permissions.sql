@@ -0,0 +1,31 @@
+alter table token_permissions add column deny boolean not null default false;
+
+create table token_folder_permissions (
+  token uuid not null,
+  folder text not null,
+
+  created_at timestamp with time zone not null default now(),
+  updated_at timestamp with time zone not null default now(),
+
+  can_read boolean not null default false,
+  can_write boolean not null default false,
+  deny boolean not null default false,
+
+  notes text,
+
+  primary key(token, folder),
+  foreign key(token) references tokens(uuid) on delete cascade,
+  constraint token_folder_permissions_folder_format
+    check (folder ~ '^[A-Za-z0-9_.-]+(/[A-Za-z0-9_.-]+)*$')
+);
+select manage_updated_at('token_folder_permissions');
+
+-- Collects all permissions that apply to a (token, secret) combination, no
+-- matter if they have been granted for the secret directly or via a folder.
+create view effective_token_permissions as
+  select token, secret, can_read, can_write, deny
+  from token_permissions
+  union all
+  select fp.token, s.uuid as secret, fp.can_read, fp.can_write, fp.deny
+  from token_folder_permissions fp
+  join secrets s on starts_with(s.path, fp.folder || '/');
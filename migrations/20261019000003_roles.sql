@@ -0,0 +1,82 @@
+create table roles (
+  uuid uuid primary key default uuid_generate_v4(),
+
+  created_at timestamp with time zone not null default now(),
+  updated_at timestamp with time zone not null default now(),
+
+  name text not null unique,
+
+  notes text
+);
+select manage_updated_at('roles');
+
+create table role_permissions (
+  role uuid not null,
+  secret uuid not null,
+
+  created_at timestamp with time zone not null default now(),
+  updated_at timestamp with time zone not null default now(),
+
+  can_read boolean not null default false,
+  can_write boolean not null default false,
+  deny boolean not null default false,
+
+  notes text,
+
+  primary key(role, secret),
+  foreign key(role) references roles(uuid) on delete cascade,
+  foreign key(secret) references secrets(uuid) on delete cascade
+);
+select manage_updated_at('role_permissions');
+
+create table role_folder_permissions (
+  role uuid not null,
+  folder text not null,
+
+  created_at timestamp with time zone not null default now(),
+  updated_at timestamp with time zone not null default now(),
+
+  can_read boolean not null default false,
+  can_write boolean not null default false,
+  deny boolean not null default false,
+
+  notes text,
+
+  primary key(role, folder),
+  foreign key(role) references roles(uuid) on delete cascade,
+  constraint role_folder_permissions_folder_format
+    check (folder ~ '^[A-Za-z0-9_.-]+(/[A-Za-z0-9_.-]+)*$')
+);
+select manage_updated_at('role_folder_permissions');
+
+create table token_roles (
+  token uuid not null,
+  role uuid not null,
+
+  created_at timestamp with time zone not null default now(),
+  updated_at timestamp with time zone not null default now(),
+
+  notes text,
+
+  primary key(token, role),
+  foreign key(token) references tokens(uuid) on delete cascade,
+  foreign key(role) references roles(uuid) on delete cascade
+);
+select manage_updated_at('token_roles');
+
+create or replace view effective_token_permissions as
+  select token, secret, can_read, can_write, deny
+  from token_permissions
+  union all
+  select fp.token, s.uuid as secret, fp.can_read, fp.can_write, fp.deny
+  from token_folder_permissions fp
+  join secrets s on starts_with(s.path, fp.folder || '/')
+  union all
+  select tr.token, rp.secret, rp.can_read, rp.can_write, rp.deny
+  from token_roles tr
+  join role_permissions rp on rp.role = tr.role
+  union all
+  select tr.token, s.uuid as secret, rfp.can_read, rfp.can_write, rfp.deny
+  from token_roles tr
+  join role_folder_permissions rfp on rfp.role = tr.role
+  join secrets s on starts_with(s.path, rfp.folder || '/');
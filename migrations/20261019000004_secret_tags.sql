@@ -0,0 +1,13 @@
+alter table secrets add column tags text[] not null default '{}';
+create index secrets_tags on secrets using gin (tags);
+
+-- Collapses all effective permissions into the final decision for each (token,
+-- secret) combination. A deny always wins over a grant.
+create view token_secret_access as
+  select
+    token,
+    secret,
+    coalesce(bool_or(can_read and not deny) and not bool_or(can_read and deny), false) as can_read,
+    coalesce(bool_or(can_write and not deny) and not bool_or(can_write and deny), false) as can_write
+  from effective_token_permissions
+  group by token, secret;
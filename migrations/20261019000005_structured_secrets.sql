@@ -0,0 +1,6 @@
+create type secret_kind as enum ('blob', 'structured');
+
+alter table secrets add column kind secret_kind not null default 'blob';
+alter table secrets add column fields jsonb;
+alter table secrets add constraint secrets_fields_kind
+  check (fields is null or (kind = 'structured' and jsonb_typeof(fields) = 'object'));
@@ -0,0 +1 @@
+alter type secret_kind add value 'template';
@@ -0,0 +1,86 @@
+create type transit_key_type as enum ('aes256_gcm', 'ed25519');
+
+create table transit_keys (
+  uuid uuid primary key default uuid_generate_v4(),
+
+  created_at timestamp with time zone not null default now(),
+  updated_at timestamp with time zone not null default now(),
+
+  name text not null unique,
+  type transit_key_type not null,
+
+  notes text,
+
+  constraint transit_keys_name_format check (name ~ '^[A-Za-z0-9_.-]+$')
+);
+select manage_updated_at('transit_keys');
+
+-- Each key can have multiple versions. New data is always encrypted or signed
+-- with the latest version, older versions are kept around for decryption and
+-- verification. The key material is 32 random bytes for both key types: the
+-- AES key itself, or the Ed25519 seed.
+create table transit_key_versions (
+  key uuid not null,
+  version integer not null,
+
+  created_at timestamp with time zone not null default now(),
+
+  key_material bytea not null default gen_random_bytes(32),
+
+  primary key(key, version),
+  foreign key(key) references transit_keys(uuid) on delete cascade,
+  constraint transit_key_versions_version_positive check (version > 0)
+);
+
+-- Adds a new version to a key. New keys get their first version automatically.
+create or replace function rotate_transit_key(_name text) returns integer as $$
+  insert into transit_key_versions (key, version)
+  select k.uuid, coalesce(max(v.version), 0) + 1
+  from transit_keys k
+  left join transit_key_versions v on v.key = k.uuid
+  where k.name = _name
+  group by k.uuid
+  returning version;
+$$ language sql;
+
+create or replace function create_first_transit_key_version() returns trigger as $$
+begin
+  insert into transit_key_versions (key, version) values (new.uuid, 1);
+  return new;
+end;
+$$ language plpgsql;
+
+create trigger create_first_version after insert on transit_keys
+  for each row execute procedure create_first_transit_key_version();
+
+create table token_transit_permissions (
+  token uuid not null,
+  key uuid not null,
+
+  created_at timestamp with time zone not null default now(),
+  updated_at timestamp with time zone not null default now(),
+
+  can_encrypt boolean not null default false,
+  can_decrypt boolean not null default false,
+  can_sign boolean not null default false,
+  can_verify boolean not null default false,
+
+  notes text,
+
+  primary key(token, key),
+  foreign key(token) references tokens(uuid) on delete cascade,
+  foreign key(key) references transit_keys(uuid) on delete cascade
+);
+select manage_updated_at('token_transit_permissions');
+
+-- Transit operations are audited as well, but they don't touch a secret.
+alter type audit_log_action add value 'transit_encrypt';
+alter type audit_log_action add value 'transit_decrypt';
+alter type audit_log_action add value 'transit_rewrap';
+alter type audit_log_action add value 'transit_sign';
+alter type audit_log_action add value 'transit_verify';
+
+alter table audit_log alter column secret drop not null;
+alter table audit_log add column transit_key uuid;
+alter table audit_log add constraint audit_log_subject
+  check (num_nonnulls(secret, transit_key) = 1);
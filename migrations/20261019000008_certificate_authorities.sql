@@ -0,0 +1,59 @@
+-- A certificate authority is backed by a structured secret, which has to hold
+-- the CA's `certificate` and `private_key` fields, both PEM-encoded.
+create table certificate_authorities (
+  uuid uuid primary key default uuid_generate_v4(),
+
+  created_at timestamp with time zone not null default now(),
+  updated_at timestamp with time zone not null default now(),
+
+  name text not null unique,
+  secret uuid not null,
+  max_ttl interval not null default '24 hours',
+
+  notes text,
+
+  foreign key(secret) references secrets(uuid),
+  constraint certificate_authorities_name_format check (name ~ '^[A-Za-z0-9_.-]+$')
+);
+select manage_updated_at('certificate_authorities');
+
+-- Allows tokens with a given role to request certificates from a CA. Entries in
+-- `allowed_names` are either exact names, or `*.example.com` to allow all names
+-- below `example.com`.
+create table role_certificate_policies (
+  role uuid not null,
+  authority uuid not null,
+
+  created_at timestamp with time zone not null default now(),
+  updated_at timestamp with time zone not null default now(),
+
+  allowed_names text[] not null default '{}',
+  max_ttl interval not null default '24 hours',
+
+  notes text,
+
+  primary key(role, authority),
+  foreign key(role) references roles(uuid) on delete cascade,
+  foreign key(authority) references certificate_authorities(uuid) on delete cascade
+);
+select manage_updated_at('role_certificate_policies');
+
+create table issued_certificates (
+  serial_number text primary key,
+  authority uuid not null,
+  token uuid,
+
+  created_at timestamp with time zone not null default now(),
+
+  names text[] not null,
+  not_before timestamp with time zone not null,
+  not_after timestamp with time zone not null,
+  revoked_at timestamp with time zone,
+
+  foreign key(authority) references certificate_authorities(uuid) on delete cascade,
+  foreign key(token) references tokens(uuid) on delete set null
+);
+create index issued_certificates_revoked on issued_certificates (authority)
+  where revoked_at is not null;
+
+alter type audit_log_action add value 'certificate_issue';
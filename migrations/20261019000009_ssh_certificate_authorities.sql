@@ -0,0 +1,49 @@
+create type ssh_certificate_type as enum ('user', 'host');
+
+-- An SSH CA is backed by a regular secret, which has to contain the CA's
+-- private key in the OpenSSH format.
+create table ssh_certificate_authorities (
+  uuid uuid primary key default uuid_generate_v4(),
+
+  created_at timestamp with time zone not null default now(),
+  updated_at timestamp with time zone not null default now(),
+
+  name text not null unique,
+  secret uuid not null,
+  max_ttl interval not null default '8 hours',
+
+  notes text,
+
+  foreign key(secret) references secrets(uuid),
+  constraint ssh_certificate_authorities_name_format check (name ~ '^[A-Za-z0-9_.-]+$')
+);
+select manage_updated_at('ssh_certificate_authorities');
+
+-- Allows tokens with a given role to get public keys signed by an SSH CA.
+-- Entries in `allowed_principals` are either exact principals, `*.example.com`
+-- to allow all host names below `example.com`, or `*` to allow everything.
+create table role_ssh_policies (
+  role uuid not null,
+  authority uuid not null,
+  certificate_type ssh_certificate_type not null,
+
+  created_at timestamp with time zone not null default now(),
+  updated_at timestamp with time zone not null default now(),
+
+  allowed_principals text[] not null default '{}',
+  allowed_extensions text[] not null default '{}',
+  max_ttl interval not null default '8 hours',
+
+  notes text,
+
+  primary key(role, authority, certificate_type),
+  foreign key(role) references roles(uuid) on delete cascade,
+  foreign key(authority) references ssh_certificate_authorities(uuid) on delete cascade
+);
+select manage_updated_at('role_ssh_policies');
+
+alter type audit_log_action add value 'ssh_certificate_sign';
+
+-- Some actions need more context than the token and the secret, like the
+-- fingerprint of a signed key.
+alter table audit_log add column details jsonb;
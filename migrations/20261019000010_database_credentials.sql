@@ -0,0 +1,65 @@
+-- A PostgreSQL database vssv can create short-lived roles in. The secret has
+-- to contain a connection URL for a user that is allowed to create roles.
+-- Statements can use `{{name}}`, `{{password}}`, and `{{expiration}}` as
+-- placeholders, which are replaced with generated values.
+create table database_connections (
+  uuid uuid primary key default uuid_generate_v4(),
+
+  created_at timestamp with time zone not null default now(),
+  updated_at timestamp with time zone not null default now(),
+
+  name text not null unique,
+  secret uuid not null,
+
+  creation_statements text not null default
+    'create role "{{name}}" with login password ''{{password}}'' valid until ''{{expiration}}'';',
+  revocation_statements text not null default
+    'select pg_terminate_backend(pid) from pg_stat_activity where usename = ''{{name}}'';
+    drop owned by "{{name}}";
+    drop role if exists "{{name}}";',
+  default_ttl interval not null default '1 hour',
+  max_ttl interval not null default '24 hours',
+
+  notes text,
+
+  foreign key(secret) references secrets(uuid),
+  constraint database_connections_name_format check (name ~ '^[A-Za-z0-9_.-]+$')
+);
+select manage_updated_at('database_connections');
+
+create table role_database_policies (
+  role uuid not null,
+  connection uuid not null,
+
+  created_at timestamp with time zone not null default now(),
+  updated_at timestamp with time zone not null default now(),
+
+  max_ttl interval not null default '1 hour',
+
+  notes text,
+
+  primary key(role, connection),
+  foreign key(role) references roles(uuid) on delete cascade,
+  foreign key(connection) references database_connections(uuid) on delete cascade
+);
+select manage_updated_at('role_database_policies');
+
+create table database_leases (
+  uuid uuid primary key default uuid_generate_v4(),
+  connection uuid not null,
+  token uuid,
+
+  created_at timestamp with time zone not null default now(),
+  expires_at timestamp with time zone not null,
+  revoked_at timestamp with time zone,
+
+  username text not null,
+
+  foreign key(connection) references database_connections(uuid),
+  foreign key(token) references tokens(uuid) on delete set null
+);
+create index database_leases_active on database_leases (expires_at)
+  where revoked_at is null;
+
+alter type audit_log_action add value 'database_credentials_create';
+alter type audit_log_action add value 'database_credentials_revoke';
@@ -0,0 +1,58 @@
+create type lease_kind as enum ('database_credentials', 'certificate');
+
+-- Everything vssv hands out for a limited time is tracked as a lease. `path`
+-- describes what the lease is for, like `database/app/vssv_abc`, and can be
+-- used to revoke many leases at once. `secret` is the secret the lease was
+-- derived from, like a database connection's URL or a CA's key. A lease can be
+-- renewed until `max_expires_at`.
+create table leases (
+  uuid uuid primary key default uuid_generate_v4(),
+  kind lease_kind not null,
+  path text not null,
+  secret uuid not null,
+  token uuid,
+
+  created_at timestamp with time zone not null default now(),
+  expires_at timestamp with time zone not null,
+  max_expires_at timestamp with time zone not null,
+  revoked_at timestamp with time zone,
+
+  foreign key(secret) references secrets(uuid),
+  foreign key(token) references tokens(uuid) on delete set null,
+  constraint leases_max_expiry check (expires_at <= max_expires_at)
+);
+create index leases_active on leases (expires_at)
+  where revoked_at is null;
+create index leases_active_path on leases (path text_pattern_ops)
+  where revoked_at is null;
+
+insert into leases (uuid, kind, path, secret, token, created_at, expires_at, max_expires_at, revoked_at)
+  select l.uuid, 'database_credentials', 'database/' || c.name || '/' || l.username, c.secret,
+    l.token, l.created_at, l.expires_at, l.expires_at, l.revoked_at
+  from database_leases l
+  join database_connections c on c.uuid = l.connection;
+
+alter table database_leases
+  drop column token,
+  drop column created_at,
+  drop column expires_at,
+  drop column revoked_at,
+  alter column uuid drop default,
+  add foreign key(uuid) references leases(uuid) on delete cascade;
+
+alter table database_connections add column renewal_statements text not null default
+  'alter role "{{name}}" valid until ''{{expiration}}'';';
+
+alter table issued_certificates add column lease uuid;
+update issued_certificates set lease = uuid_generate_v4();
+insert into leases (uuid, kind, path, secret, token, created_at, expires_at, max_expires_at, revoked_at)
+  select ic.lease, 'certificate', 'pki/' || ca.name || '/' || ic.serial_number, ca.secret,
+    ic.token, ic.created_at, ic.not_after, ic.not_after, ic.revoked_at
+  from issued_certificates ic
+  join certificate_authorities ca on ca.uuid = ic.authority;
+alter table issued_certificates
+  alter column lease set not null,
+  add foreign key(lease) references leases(uuid) on delete cascade;
+
+alter type audit_log_action add value 'certificate_revoke';
+alter type audit_log_action add value 'lease_renew';
@@ -0,0 +1,37 @@
+-- Child tokens are derived from a parent token, and get deleted together with
+-- it. They can never do more than any of their ancestors.
+alter table tokens add column parent uuid references tokens(uuid) on delete cascade;
+create index tokens_parent on tokens (parent) where parent is not null;
+
+create view token_ancestors as
+  with recursive ancestors (token, ancestor) as (
+    select uuid, parent from tokens where parent is not null
+    union
+    select a.token, t.parent
+    from ancestors a
+    join tokens t on t.uuid = a.ancestor
+    where t.parent is not null
+  )
+  select token, ancestor from ancestors;
+
+alter view token_secret_access rename to token_own_secret_access;
+
+-- A token's access to a secret, limited by the access of all its ancestors.
+-- Superuser ancestors don't limit anything.
+create view token_secret_access as
+  select
+    o.token,
+    o.secret,
+    o.can_read and not exists (
+      select 1 from token_ancestors ta
+      join tokens t on t.uuid = ta.ancestor
+      left join token_own_secret_access p on p.token = ta.ancestor and p.secret = o.secret
+      where ta.token = o.token and not t.superuser and not coalesce(p.can_read, false)
+    ) as can_read,
+    o.can_write and not exists (
+      select 1 from token_ancestors ta
+      join tokens t on t.uuid = ta.ancestor
+      left join token_own_secret_access p on p.token = ta.ancestor and p.secret = o.secret
+      where ta.token = o.token and not t.superuser and not coalesce(p.can_write, false)
+    ) as can_write
+  from token_own_secret_access o;
@@ -0,0 +1,46 @@
+-- Issuers whose JWTs can be exchanged for short-lived tokens. `issuer` has to
+-- match the JWTs' `iss` claim. The signing keys are either stored as a JWKS
+-- document in `jwks`, or fetched from `jwks_url` on every login.
+create table jwt_issuers (
+  uuid uuid primary key default uuid_generate_v4(),
+
+  created_at timestamp with time zone not null default now(),
+  updated_at timestamp with time zone not null default now(),
+
+  name text not null unique,
+  issuer text not null unique,
+  jwks jsonb,
+  jwks_url text,
+  token_ttl interval not null default '15 minutes',
+
+  notes text,
+
+  constraint jwt_issuers_name_format check (name ~ '^[A-Za-z0-9_.-]+$'),
+  constraint jwt_issuers_keys check (num_nonnulls(jwks, jwks_url) = 1)
+);
+select manage_updated_at('jwt_issuers');
+
+-- Grants a role to tokens created from matching JWTs. A JWT matches if its
+-- `aud` contains `audience`, its `sub` matches `subject_pattern`, where `*`
+-- matches anything, and all `claims` have exactly the given values.
+create table jwt_bindings (
+  uuid uuid primary key default uuid_generate_v4(),
+  issuer uuid not null,
+  role uuid not null,
+
+  created_at timestamp with time zone not null default now(),
+  updated_at timestamp with time zone not null default now(),
+
+  audience text not null,
+  subject_pattern text not null default '*',
+  claims jsonb not null default '{}',
+
+  notes text,
+
+  foreign key(issuer) references jwt_issuers(uuid) on delete cascade,
+  foreign key(role) references roles(uuid) on delete cascade,
+  constraint jwt_bindings_claims check (jsonb_typeof(claims) = 'object')
+);
+select manage_updated_at('jwt_bindings');
+
+alter table tokens add column jwt_issuer uuid references jwt_issuers(uuid) on delete cascade;
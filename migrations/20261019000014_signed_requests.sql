@@ -0,0 +1,16 @@
+-- Tokens that are only accepted on signed requests, and never as a bearer
+-- token.
+alter table tokens add column require_signed_requests boolean not null default false;
+
+-- Nonces of recently signed requests, used to reject replays. Entries older
+-- than the replay window are useless, and get cleaned up on the next request
+-- by the same token.
+create table request_nonces (
+  token uuid not null,
+  nonce text not null,
+
+  created_at timestamp with time zone not null default now(),
+
+  primary key(token, nonce),
+  foreign key(token) references tokens(uuid) on delete cascade
+);
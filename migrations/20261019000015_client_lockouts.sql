@@ -0,0 +1,7 @@
+-- Lockouts of client addresses aren't tied to a token or a subject, so those
+-- columns can be empty now.
+alter type audit_log_action add value 'client_lockout';
+alter table audit_log alter column token drop not null;
+alter table audit_log drop constraint audit_log_subject;
+alter table audit_log add constraint audit_log_subject
+  check (num_nonnulls(secret, transit_key) <= 1);
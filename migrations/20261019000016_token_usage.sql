@@ -0,0 +1,13 @@
+-- Usage statistics for tokens, to find abandoned tokens and tokens that are
+-- used from unexpected places.
+alter table tokens
+  add column last_client_addr inet,
+  add column last_user_agent text,
+  add column expired_used_at timestamp with time zone;
+
+create table token_usage (
+  token uuid not null references tokens(uuid) on delete cascade,
+  day date not null,
+  requests bigint not null default 0,
+  primary key (token, day)
+);
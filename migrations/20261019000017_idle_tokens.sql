@@ -0,0 +1,3 @@
+-- Tokens that have been idle for too long can be disabled automatically. They
+-- are kept, with all their permissions, but rejected.
+alter table tokens add column disabled_at timestamp with time zone;
@@ -0,0 +1,8 @@
+-- Suspended tokens are rejected, but keep their permissions, so they can be
+-- investigated, and reinstated later. This replaces disabling idle tokens.
+alter table tokens rename column disabled_at to suspended_at;
+alter table tokens add column suspended_reason text;
+update tokens set suspended_reason = 'idle' where suspended_at is not null;
+
+alter type audit_log_action add value 'token_suspend';
+alter type audit_log_action add value 'token_unsuspend';
@@ -0,0 +1,138 @@
+-- Access schedules limit when tokens and grants can be used, for example to
+-- maintenance windows. A schedule is open while any of its windows is.
+create table access_schedules (
+  uuid uuid primary key default uuid_generate_v4(),
+
+  created_at timestamp with time zone not null default now(),
+  updated_at timestamp with time zone not null default now(),
+
+  name text not null unique,
+  timezone text not null default 'UTC',
+
+  notes text,
+
+  -- Fails for unknown time zones.
+  constraint access_schedules_timezone check ((now() at time zone timezone) is not null)
+);
+select manage_updated_at('access_schedules');
+
+-- A window is open on the given ISO weekdays (1 is Monday) between starts_at
+-- and ends_at, in the schedule's time zone. Windows that end before they start
+-- cross midnight, and belong to the weekday they start on. valid_from and
+-- valid_until optionally limit the window to a range of dates.
+create table access_schedule_windows (
+  uuid uuid primary key default uuid_generate_v4(),
+  schedule uuid not null references access_schedules(uuid) on delete cascade,
+
+  weekdays smallint[] not null default '{1,2,3,4,5,6,7}',
+  starts_at time not null default '00:00',
+  ends_at time not null default '24:00',
+  valid_from date,
+  valid_until date,
+
+  notes text,
+
+  constraint access_schedule_windows_weekdays check (weekdays <@ '{1,2,3,4,5,6,7}')
+);
+create index access_schedule_windows_schedule on access_schedule_windows (schedule);
+
+-- Checks if a schedule is open right now. No schedule is always open.
+create function access_schedule_open(_schedule uuid) returns boolean as $$
+  select _schedule is null or exists (
+    select 1
+    from access_schedules s
+    join access_schedule_windows w on w.schedule = s.uuid
+    cross join lateral (select now() at time zone s.timezone as local_now) l
+    where s.uuid = _schedule
+      and (w.valid_from is null or l.local_now::date >= w.valid_from)
+      and (w.valid_until is null or l.local_now::date <= w.valid_until)
+      and case
+        when w.starts_at < w.ends_at then
+          extract(isodow from l.local_now)::smallint = any(w.weekdays)
+          and l.local_now::time >= w.starts_at and l.local_now::time < w.ends_at
+        else
+          (extract(isodow from l.local_now)::smallint = any(w.weekdays)
+            and l.local_now::time >= w.starts_at)
+          or (extract(isodow from l.local_now - interval '1 day')::smallint = any(w.weekdays)
+            and l.local_now::time < w.ends_at)
+      end
+  );
+$$ language sql stable;
+
+-- Schedules can't be deleted while they're in use, as that would lift the
+-- restriction.
+alter table tokens add column access_schedule uuid references access_schedules(uuid);
+alter table token_permissions add column access_schedule uuid references access_schedules(uuid);
+alter table token_folder_permissions add column access_schedule uuid references access_schedules(uuid);
+alter table role_permissions add column access_schedule uuid references access_schedules(uuid);
+alter table role_folder_permissions add column access_schedule uuid references access_schedules(uuid);
+
+create or replace view effective_token_permissions as
+  select token, secret, can_read, can_write, deny, access_schedule
+  from token_permissions
+  union all
+  select fp.token, s.uuid as secret, fp.can_read, fp.can_write, fp.deny, fp.access_schedule
+  from token_folder_permissions fp
+  join secrets s on starts_with(s.path, fp.folder || '/')
+  union all
+  select tr.token, rp.secret, rp.can_read, rp.can_write, rp.deny, rp.access_schedule
+  from token_roles tr
+  join role_permissions rp on rp.role = tr.role
+  union all
+  select tr.token, s.uuid as secret, rfp.can_read, rfp.can_write, rfp.deny, rfp.access_schedule
+  from token_roles tr
+  join role_folder_permissions rfp on rfp.role = tr.role
+  join secrets s on starts_with(s.path, rfp.folder || '/');
+
+-- Grants only count while their schedule is open, denies always do. The
+-- *_any_time columns ignore schedules, so denials can be told apart.
+create or replace view token_own_secret_access as
+  select
+    token,
+    secret,
+    coalesce(
+      bool_or(can_read and not deny and access_schedule_open(access_schedule))
+        and not bool_or(can_read and deny),
+      false
+    ) as can_read,
+    coalesce(
+      bool_or(can_write and not deny and access_schedule_open(access_schedule))
+        and not bool_or(can_write and deny),
+      false
+    ) as can_write,
+    coalesce(bool_or(can_read and not deny) and not bool_or(can_read and deny), false) as can_read_any_time,
+    coalesce(bool_or(can_write and not deny) and not bool_or(can_write and deny), false) as can_write_any_time
+  from effective_token_permissions
+  group by token, secret;
+
+create or replace view token_secret_access as
+  select
+    o.token,
+    o.secret,
+    o.can_read and not exists (
+      select 1 from token_ancestors ta
+      join tokens t on t.uuid = ta.ancestor
+      left join token_own_secret_access p on p.token = ta.ancestor and p.secret = o.secret
+      where ta.token = o.token and not t.superuser and not coalesce(p.can_read, false)
+    ) as can_read,
+    o.can_write and not exists (
+      select 1 from token_ancestors ta
+      join tokens t on t.uuid = ta.ancestor
+      left join token_own_secret_access p on p.token = ta.ancestor and p.secret = o.secret
+      where ta.token = o.token and not t.superuser and not coalesce(p.can_write, false)
+    ) as can_write,
+    o.can_read_any_time and not exists (
+      select 1 from token_ancestors ta
+      join tokens t on t.uuid = ta.ancestor
+      left join token_own_secret_access p on p.token = ta.ancestor and p.secret = o.secret
+      where ta.token = o.token and not t.superuser and not coalesce(p.can_read_any_time, false)
+    ) as can_read_any_time,
+    o.can_write_any_time and not exists (
+      select 1 from token_ancestors ta
+      join tokens t on t.uuid = ta.ancestor
+      left join token_own_secret_access p on p.token = ta.ancestor and p.secret = o.secret
+      where ta.token = o.token and not t.superuser and not coalesce(p.can_write_any_time, false)
+    ) as can_write_any_time
+  from token_own_secret_access o;
+
+alter type audit_log_action add value 'access_denied';
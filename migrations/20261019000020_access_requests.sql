@@ -0,0 +1,25 @@
+-- Secrets that require approval can only be read after a second token with
+-- approver rights has approved a request for it. Every approved request allows
+-- a single read.
+alter table secrets add column requires_approval boolean not null default false;
+alter table tokens add column can_approve boolean not null default false;
+
+create table access_requests (
+  uuid uuid primary key default uuid_generate_v4(),
+  secret uuid not null references secrets(uuid) on delete cascade,
+  token uuid not null references tokens(uuid) on delete cascade,
+
+  created_at timestamp with time zone not null default now(),
+  expires_at timestamp with time zone not null,
+
+  approved_by uuid references tokens(uuid) on delete set null,
+  approved_at timestamp with time zone,
+  read_until timestamp with time zone,
+  read_at timestamp with time zone,
+
+  constraint access_requests_approver check (approved_by is distinct from token)
+);
+create index access_requests_token_secret on access_requests (token, secret);
+
+alter type audit_log_action add value 'access_request';
+alter type audit_log_action add value 'access_approve';
@@ -0,0 +1,9 @@
+-- Break-glass tokens can read any secret in an emergency, as long as they
+-- give a justification. Those reads are logged with a high severity, so they
+-- stand out in the audit log.
+alter table tokens add column break_glass boolean not null default false;
+
+create type audit_log_severity as enum ('normal', 'high');
+alter table audit_log add column severity audit_log_severity not null default 'normal';
+
+alter type audit_log_action add value 'break_glass_read';
@@ -0,0 +1,9 @@
+-- Nonces have to be kept until the signed timestamp leaves the replay window,
+-- which can be up to twice the window after they were first seen, if the
+-- timestamp is in the future. Existing nonces are kept for a day, which is
+-- longer than any sensible window.
+alter table request_nonces add column expires_at timestamp with time zone;
+update request_nonces set expires_at = created_at + interval '1 day';
+alter table request_nonces alter column expires_at set not null;
+
+create index on request_nonces (expires_at);
@@ -0,0 +1,4 @@
+-- Requesting a wildcard certificate like `*.example.com` has to be allowed
+-- explicitly. Otherwise, a role that may only request certificates for hosts
+-- below a domain could get one certificate that's valid for all of them.
+alter table role_certificate_policies add column allow_wildcards boolean not null default false;
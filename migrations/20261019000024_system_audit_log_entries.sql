@@ -0,0 +1,3 @@
+-- Some actions aren't triggered by a request, like suspending idle tokens, so
+-- there is no client address to log for them.
+alter table audit_log alter column client_addr drop not null;
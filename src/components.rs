@@ -1,2 +1,8 @@
 pub mod app_state;
+pub mod crl_cache;
+pub mod lease_sweeper;
+pub mod nonce_sweeper;
+pub mod notifier;
+pub mod rate_limiter;
 pub mod settings;
+pub mod token_sweeper;
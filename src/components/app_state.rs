@@ -5,4 +5,6 @@ use std::sync::Arc;
 pub struct AppState {
     pub database: sqlx::PgPool,
     pub settings: Arc<super::settings::Settings>,
+    pub rate_limiter: Arc<super::rate_limiter::RateLimiter>,
+    pub crl_cache: Arc<super::crl_cache::CrlCache>,
 }
@@ -0,0 +1,53 @@
+use std::{collections::HashMap, sync::Mutex};
+
+use chrono::{DateTime, Duration, Utc};
+use uuid::Uuid;
+
+use crate::entities::RevocationState;
+
+/// How long a signed CRL is served before it's signed again, even if nothing
+/// was revoked. This is well below the CRL's validity, so clients never see a
+/// CRL that's about to expire.
+const CRL_REFRESH_INTERVAL: Duration = Duration::hours(1);
+
+#[derive(Debug)]
+struct CachedCrl {
+    revocations: RevocationState,
+    refresh_at: DateTime<Utc>,
+    der: Vec<u8>,
+}
+
+/// Signed CRLs, cached per CA. Signing a CRL requires loading the CA's private
+/// key, which is too expensive to do on every request to an endpoint that
+/// doesn't require a token. A cached CRL is served until the CA's revoked
+/// certificates change, or until it's due for a refresh. Each instance keeps
+/// its own cache.
+#[derive(Debug, Default)]
+pub struct CrlCache {
+    entries: Mutex<HashMap<Uuid, CachedCrl>>,
+}
+
+impl CrlCache {
+    /// Returns the cached CRL of a CA, if it's still up to date with the CA's
+    /// revocations.
+    pub fn get(&self, authority: Uuid, revocations: &RevocationState) -> Option<Vec<u8>> {
+        let entries = self.entries.lock().expect("CRL cache lock poisoned");
+        entries
+            .get(&authority)
+            .filter(|cached| cached.revocations == *revocations && cached.refresh_at > Utc::now())
+            .map(|cached| cached.der.clone())
+    }
+
+    /// Stores a freshly signed CRL of a CA, replacing the previous one.
+    pub fn insert(&self, authority: Uuid, revocations: RevocationState, der: Vec<u8>) {
+        let mut entries = self.entries.lock().expect("CRL cache lock poisoned");
+        entries.insert(
+            authority,
+            CachedCrl {
+                revocations,
+                refresh_at: Utc::now() + CRL_REFRESH_INTERVAL,
+                der,
+            },
+        );
+    }
+}
@@ -0,0 +1,59 @@
+use std::time::Duration;
+
+use sqlx::{Acquire, PgPool};
+use tracing::{debug, error, info};
+
+use crate::{entities::Lease, errors::ResponseError};
+
+/// The key of the advisory lock that makes sure only one vssv instance sweeps
+/// at a time. It's just "vssv" in ASCII.
+const SWEEP_LOCK_KEY: i64 = 0x7673_7376;
+
+/// Runs forever, and cleans up after all expired leases every `interval`. This
+/// should be spawned as a background task when the server starts.
+pub async fn run(database: PgPool, interval: Duration) {
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        ticker.tick().await;
+        if let Err(e) = sweep(&database).await {
+            error!("lease sweep failed: {:?}", e);
+        }
+    }
+}
+
+/// Cleans up after all expired leases. If another instance is sweeping right
+/// now, this does nothing. Leases that fail to expire, for example because the
+/// target database is unreachable, are logged and retried during the next
+/// sweep. Every lease is expired in its own savepoint, so a failed lease
+/// doesn't abort the whole sweep.
+async fn sweep(database: &PgPool) -> Result<(), ResponseError> {
+    let mut tx = database.begin().await?;
+
+    let locked = sqlx::query_scalar!(
+        r#"select pg_try_advisory_xact_lock($1) as "locked!""#,
+        SWEEP_LOCK_KEY
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    if !locked {
+        debug!("skipping lease sweep, another instance is sweeping");
+        return Ok(());
+    }
+
+    for lease in Lease::find_expired_for_update(&mut tx).await? {
+        let mut savepoint = tx.begin().await?;
+        match lease.expire(&mut savepoint).await {
+            Ok(()) => {
+                savepoint.commit().await?;
+                info!("expired lease=`{}` for path=`{}`", lease.uuid, lease.path);
+            }
+            Err(e) => {
+                savepoint.rollback().await?;
+                error!("could not expire lease=`{}`: {:?}", lease.uuid, e);
+            }
+        }
+    }
+    tx.commit().await?;
+
+    Ok(())
+}
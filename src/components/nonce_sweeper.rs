@@ -0,0 +1,24 @@
+use std::time::Duration;
+
+use sqlx::PgPool;
+use tracing::{debug, error};
+
+use crate::entities::delete_expired_nonces;
+
+/// How often expired nonces are deleted. Expired nonces don't block new
+/// requests, so this only keeps the table small.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Runs forever, and deletes the nonces of signed requests that left the
+/// replay window every minute. This should be spawned as a background task
+/// when the server starts. Running this on multiple instances is fine.
+pub async fn run(database: PgPool) {
+    let mut ticker = tokio::time::interval(SWEEP_INTERVAL);
+    loop {
+        ticker.tick().await;
+        match delete_expired_nonces(&database).await {
+            Ok(deleted) => debug!("deleted {} expired request nonces", deleted),
+            Err(e) => error!("request nonce sweep failed: {:?}", e),
+        }
+    }
+}
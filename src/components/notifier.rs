@@ -0,0 +1,37 @@
+use std::time::Duration;
+
+use reqwest::Url;
+use tracing::{error, warn};
+
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Sends a security-relevant event as JSON to all given webhooks, and logs it.
+/// Deliveries happen in the background, so the request that triggered the
+/// event doesn't have to wait for slow receivers. Failed deliveries are only
+/// logged, as the event is in the audit log anyway.
+pub fn notify(webhooks: &[Url], event: serde_json::Value) {
+    warn!("security event: {}", event);
+
+    for webhook in webhooks {
+        let webhook = webhook.clone();
+        let event = event.clone();
+        tokio::spawn(async move {
+            if let Err(e) = deliver(&webhook, &event).await {
+                error!("could not deliver event to webhook=`{}`: {}", webhook, e);
+            }
+        });
+    }
+}
+
+async fn deliver(webhook: &Url, event: &serde_json::Value) -> Result<(), reqwest::Error> {
+    reqwest::Client::builder()
+        .timeout(WEBHOOK_TIMEOUT)
+        .build()?
+        .post(webhook.clone())
+        .json(event)
+        .send()
+        .await?
+        .error_for_status()?;
+
+    Ok(())
+}
@@ -0,0 +1,340 @@
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    net::IpAddr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use serde_json::json;
+use sqlx::PgPool;
+use tracing::warn;
+use uuid::Uuid;
+
+use crate::{
+    components::settings::Settings,
+    entities::{AuditLogAction, AuditLogEntry},
+    errors::ResponseError,
+};
+
+/// The window the per-IP and per-token limits apply to.
+const RATE_LIMIT_WINDOW: Duration = Duration::from_secs(60);
+
+/// Once a map holds more entries than this, expired entries get pruned.
+const PRUNE_THRESHOLD: usize = 10_000;
+
+/// Counts events in a fixed window.
+#[derive(Debug)]
+struct Window {
+    started: Instant,
+    count: u32,
+}
+
+/// A set of fixed-window counters, one per key.
+#[derive(Debug)]
+struct Counters<K> {
+    length: Duration,
+    windows: HashMap<K, Window>,
+}
+
+impl<K: Eq + Hash> Counters<K> {
+    fn new(length: Duration) -> Self {
+        Self {
+            length,
+            windows: HashMap::new(),
+        }
+    }
+
+    /// Counts an event for `key`, and returns the number of events in the
+    /// current window, including this one.
+    fn hit(&mut self, key: K, now: Instant) -> u32 {
+        if self.windows.len() > PRUNE_THRESHOLD {
+            let length = self.length;
+            self.windows
+                .retain(|_, window| now.duration_since(window.started) < length);
+        }
+
+        let window = self.windows.entry(key).or_insert(Window {
+            started: now,
+            count: 0,
+        });
+        if now.duration_since(window.started) >= self.length {
+            *window = Window {
+                started: now,
+                count: 0,
+            };
+        }
+        window.count += 1;
+        window.count
+    }
+
+    fn reset(&mut self, key: &K) {
+        self.windows.remove(key);
+    }
+}
+
+#[derive(Debug)]
+struct LimiterState {
+    requests_by_ip: Counters<IpAddr>,
+    requests_by_token: Counters<Uuid>,
+    failures_by_ip: Counters<IpAddr>,
+    locked_until: HashMap<IpAddr, Instant>,
+}
+
+impl LimiterState {
+    /// Locks out a client address until the given time. Just like the
+    /// counters, expired lockouts get pruned once there are too many of them,
+    /// so addresses that never come back don't stay around forever.
+    fn lock_out(&mut self, ip: IpAddr, until: Instant, now: Instant) {
+        if self.locked_until.len() > PRUNE_THRESHOLD {
+            self.locked_until
+                .retain(|_, locked_until| *locked_until > now);
+        }
+
+        self.locked_until.insert(ip, until);
+    }
+}
+
+/// In-memory rate limits for everything that looks up tokens. Each instance
+/// keeps its own counters, so with multiple instances, the effective limits
+/// are multiplied by the number of instances. A limit of 0 disables it.
+#[derive(Debug)]
+pub struct RateLimiter {
+    per_ip: u32,
+    per_token: u32,
+    lockout_threshold: u32,
+    lockout_duration: Duration,
+    state: Mutex<LimiterState>,
+}
+
+impl RateLimiter {
+    pub fn new(settings: &Settings) -> Self {
+        let lockout_duration = Duration::from_secs(settings.lockout_duration);
+        Self {
+            per_ip: settings.rate_limit_per_ip,
+            per_token: settings.rate_limit_per_token,
+            lockout_threshold: settings.lockout_threshold,
+            lockout_duration,
+            state: Mutex::new(LimiterState {
+                requests_by_ip: Counters::new(RATE_LIMIT_WINDOW),
+                requests_by_token: Counters::new(RATE_LIMIT_WINDOW),
+                failures_by_ip: Counters::new(lockout_duration),
+                locked_until: HashMap::new(),
+            }),
+        }
+    }
+
+    /// Counts a request from a client address. Fails if the address is locked
+    /// out, or if it sent too many requests in the current window.
+    pub fn check_ip(&self, ip: IpAddr) -> Result<(), ResponseError> {
+        let now = Instant::now();
+        let mut state = self.state.lock().expect("rate limiter lock poisoned");
+
+        if let Some(locked_until) = state.locked_until.get(&ip) {
+            if *locked_until > now {
+                warn!("request from locked out client=`{}`", ip);
+                return Err(ResponseError::TooManyRequests());
+            }
+            state.locked_until.remove(&ip);
+        }
+
+        if self.per_ip > 0 && state.requests_by_ip.hit(ip, now) > self.per_ip {
+            warn!("rate limit exceeded for client=`{}`", ip);
+            return Err(ResponseError::TooManyRequests());
+        }
+
+        Ok(())
+    }
+
+    /// Counts a request by a valid token. Fails if the token was used too
+    /// often in the current window.
+    pub fn check_token(&self, token: Uuid) -> Result<(), ResponseError> {
+        let mut state = self.state.lock().expect("rate limiter lock poisoned");
+        if self.per_token > 0 && state.requests_by_token.hit(token, Instant::now()) > self.per_token
+        {
+            warn!("rate limit exceeded for token=`{}`", token);
+            return Err(ResponseError::TooManyRequests());
+        }
+
+        Ok(())
+    }
+
+    /// Counts an invalid token presented by a client address. Once the address
+    /// presented too many invalid tokens, it gets locked out, which is logged
+    /// in the audit log.
+    pub async fn record_invalid_token(
+        &self,
+        database: &PgPool,
+        ip: IpAddr,
+    ) -> Result<(), sqlx::Error> {
+        if self.lockout_threshold == 0 {
+            return Ok(());
+        }
+
+        let failures = {
+            let now = Instant::now();
+            let mut state = self.state.lock().expect("rate limiter lock poisoned");
+            let failures = state.failures_by_ip.hit(ip, now);
+            if failures < self.lockout_threshold {
+                return Ok(());
+            }
+
+            state.failures_by_ip.reset(&ip);
+            state.lock_out(ip, now + self.lockout_duration, now);
+            failures
+        };
+
+        warn!(
+            "locking out client=`{}` for {} seconds after {} invalid tokens",
+            ip,
+            self.lockout_duration.as_secs(),
+            failures
+        );
+        let _ = AuditLogEntry::log_client_action(
+            database,
+            ip,
+            AuditLogAction::ClientLockout,
+            json!({
+                "invalid_tokens": failures,
+                "lockout_seconds": self.lockout_duration.as_secs(),
+            }),
+        )
+        .await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::*;
+
+    const IP: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+    const OTHER_IP: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 2));
+
+    fn limiter(per_ip: u32, per_token: u32, lockout_threshold: u32) -> RateLimiter {
+        let lockout_duration = Duration::from_secs(60);
+        RateLimiter {
+            per_ip,
+            per_token,
+            lockout_threshold,
+            lockout_duration,
+            state: Mutex::new(LimiterState {
+                requests_by_ip: Counters::new(RATE_LIMIT_WINDOW),
+                requests_by_token: Counters::new(RATE_LIMIT_WINDOW),
+                failures_by_ip: Counters::new(lockout_duration),
+                locked_until: HashMap::new(),
+            }),
+        }
+    }
+
+    #[test]
+    fn counts_per_key_and_window() {
+        let mut counters = Counters::new(Duration::from_secs(10));
+        let start = Instant::now();
+
+        assert_eq!(counters.hit("a", start), 1);
+        assert_eq!(counters.hit("a", start + Duration::from_secs(9)), 2);
+        assert_eq!(counters.hit("b", start + Duration::from_secs(9)), 1);
+        assert_eq!(counters.hit("a", start + Duration::from_secs(10)), 1);
+
+        counters.reset(&"a");
+        assert_eq!(counters.hit("a", start + Duration::from_secs(11)), 1);
+    }
+
+    #[test]
+    fn prunes_expired_windows() {
+        let mut counters = Counters::new(Duration::from_secs(10));
+        let start = Instant::now();
+        for key in 0..=PRUNE_THRESHOLD {
+            counters.hit(key, start);
+        }
+        assert_eq!(counters.windows.len(), PRUNE_THRESHOLD + 1);
+
+        counters.hit(0, start + Duration::from_secs(5));
+        assert_eq!(counters.windows.len(), PRUNE_THRESHOLD + 1);
+
+        counters.hit(0, start + Duration::from_secs(10));
+        assert_eq!(counters.windows.len(), 1);
+    }
+
+    #[test]
+    fn prunes_expired_lockouts() {
+        let limiter = limiter(0, 0, 0);
+        let mut state = limiter.state.lock().unwrap();
+        let start = Instant::now();
+        for n in 0..=PRUNE_THRESHOLD as u32 {
+            let ip = IpAddr::V4(Ipv4Addr::from(n));
+            state.lock_out(ip, start + Duration::from_secs(10), start);
+        }
+        assert_eq!(state.locked_until.len(), PRUNE_THRESHOLD + 1);
+
+        state.lock_out(
+            IP,
+            start + Duration::from_secs(15),
+            start + Duration::from_secs(5),
+        );
+        assert_eq!(state.locked_until.len(), PRUNE_THRESHOLD + 2);
+
+        state.lock_out(
+            OTHER_IP,
+            start + Duration::from_secs(20),
+            start + Duration::from_secs(10),
+        );
+        assert_eq!(state.locked_until.len(), 2);
+        assert!(state.locked_until.contains_key(&IP));
+    }
+
+    #[test]
+    fn limits_requests_per_ip() {
+        let limiter = limiter(2, 0, 0);
+        assert!(limiter.check_ip(IP).is_ok());
+        assert!(limiter.check_ip(IP).is_ok());
+        assert!(matches!(
+            limiter.check_ip(IP),
+            Err(ResponseError::TooManyRequests())
+        ));
+        assert!(limiter.check_ip(OTHER_IP).is_ok());
+    }
+
+    #[test]
+    fn limits_requests_per_token() {
+        let limiter = limiter(0, 1, 0);
+        let token = Uuid::new_v4();
+        assert!(limiter.check_token(token).is_ok());
+        assert!(limiter.check_token(token).is_err());
+        assert!(limiter.check_token(Uuid::new_v4()).is_ok());
+    }
+
+    #[test]
+    fn zero_disables_limits() {
+        let limiter = limiter(0, 0, 0);
+        for _ in 0..100 {
+            assert!(limiter.check_ip(IP).is_ok());
+            assert!(limiter.check_token(Uuid::nil()).is_ok());
+        }
+    }
+
+    #[tokio::test]
+    async fn locks_out_after_invalid_tokens() {
+        // The lockout is in effect before it's written to the audit log, so
+        // this doesn't need a working database.
+        let database = sqlx::postgres::PgPoolOptions::new()
+            .acquire_timeout(Duration::from_millis(100))
+            .connect_lazy("postgres://127.0.0.1:1/none")
+            .unwrap();
+        let limiter = limiter(0, 0, 2);
+
+        assert!(limiter.record_invalid_token(&database, IP).await.is_ok());
+        assert!(limiter.check_ip(IP).is_ok());
+
+        let _ = limiter.record_invalid_token(&database, IP).await;
+        assert!(matches!(
+            limiter.check_ip(IP),
+            Err(ResponseError::TooManyRequests())
+        ));
+        assert!(limiter.check_ip(OTHER_IP).is_ok());
+    }
+}
@@ -1,5 +1,6 @@
 use std::net::SocketAddr;
 
+use reqwest::Url;
 use sqlx::postgres::PgConnectOptions;
 
 /// Specifies the log's output format
@@ -34,14 +35,64 @@ impl LogLevel {
     }
 }
 
+/// Commands that can be run instead of starting the server
+#[derive(clap::Subcommand, Clone, Debug)]
+pub enum Command {
+    /// Prints a report of stale and unused tokens as JSON, and exits
+    StaleTokens {
+        /// Tokens that haven't been used in this many days are reported as
+        /// unused
+        #[clap(long, default_value_t = 90)]
+        idle_days: i32,
+
+        /// Tokens that expire within this many days are reported as expiring
+        /// soon
+        #[clap(long, default_value_t = 14)]
+        expiring_within_days: i32,
+    },
+}
+
 #[derive(Clone, Debug, clap::Parser)]
 #[clap(about, version, propagate_version = true)]
 pub struct Settings {
+    /// How long an approved access request can be used to read a secret, in
+    /// seconds
+    #[clap(long, env = "APPROVAL_WINDOW", default_value_t = 900)]
+    pub approval_window: i64,
+
+    /// Webhook URLs that get notified about every break-glass read, separated
+    /// by commas
+    #[clap(long, env = "BREAK_GLASS_WEBHOOKS", value_delimiter = ',')]
+    pub break_glass_webhooks: Vec<Url>,
+
+    /// Runs a command instead of starting the server
+    #[clap(subcommand)]
+    pub command: Option<Command>,
+
     /// The database URL to connect to. Needs to be a valid PostgreSQL
     /// connection URL, like `postgres://postgres@127.0.0.1/vssv`
     #[clap(long, env = "DATABASE_URL")]
     pub database_url: PgConnectOptions,
 
+    /// If set, tokens that haven't been used for this many days get suspended
+    /// automatically, except for superuser tokens
+    #[clap(long, env = "IDLE_TOKEN_SUSPEND_DAYS")]
+    pub idle_token_suspend_days: Option<i32>,
+
+    /// How often expired leases are revoked, in seconds
+    #[clap(long, env = "LEASE_SWEEP_INTERVAL", default_value_t = 30)]
+    pub lease_sweep_interval: u64,
+
+    /// How long a client address is locked out after presenting too many
+    /// invalid tokens, in seconds
+    #[clap(long, env = "LOCKOUT_DURATION", default_value_t = 900)]
+    pub lockout_duration: u64,
+
+    /// How many invalid tokens a client address can present within the lockout
+    /// duration before it gets locked out - 0 disables lockouts
+    #[clap(long, env = "LOCKOUT_THRESHOLD", default_value_t = 10)]
+    pub lockout_threshold: u32,
+
     /// The Socket address the server should listen on
     #[clap(long, env = "LISTEN", default_value = "[::1]:8081")]
     pub listen: SocketAddr,
@@ -54,6 +105,20 @@ pub struct Settings {
     #[clap(value_enum, long, env = "LOG_LEVEL", default_value_t = LogLevel::Info)]
     pub log_level: LogLevel,
 
+    /// How many authenticated requests a client address can make per minute -
+    /// 0 disables the limit
+    #[clap(long, env = "RATE_LIMIT_PER_IP", default_value_t = 600)]
+    pub rate_limit_per_ip: u32,
+
+    /// How many requests a single token can make per minute - 0 disables the
+    /// limit
+    #[clap(long, env = "RATE_LIMIT_PER_TOKEN", default_value_t = 600)]
+    pub rate_limit_per_token: u32,
+
+    /// How far the timestamp of a signed request can be off, in seconds
+    #[clap(long, env = "SIGNATURE_MAX_AGE", default_value_t = 300)]
+    pub signature_max_age: i64,
+
     /// Limits the number of threads used - defaults to the number of CPU cores
     #[clap(long, env = "THREADS")]
     pub threads: Option<usize>,
@@ -0,0 +1,47 @@
+use std::time::Duration;
+
+use serde_json::json;
+use sqlx::PgPool;
+use tracing::{error, info};
+
+use crate::entities::{AuditLogAction, AuditLogEntry, Token};
+
+/// How often idle tokens are looked for. Idle thresholds are measured in days,
+/// so there is no need to check more often.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Runs forever, and suspends all tokens that have been idle for longer than
+/// `idle_days` days every hour. This should be spawned as a background task
+/// when the server starts, if the policy is turned on. Running this on
+/// multiple instances is fine, as every token is only suspended once.
+pub async fn run(database: PgPool, idle_days: i32) {
+    let mut ticker = tokio::time::interval(SWEEP_INTERVAL);
+    loop {
+        ticker.tick().await;
+        if let Err(e) = sweep(&database, idle_days).await {
+            error!("idle token sweep failed: {:?}", e);
+        }
+    }
+}
+
+/// Suspends all idle tokens, and logs every suspension in the audit log, just
+/// like manual suspensions, with `idle` as the reason.
+async fn sweep(database: &PgPool, idle_days: i32) -> Result<(), sqlx::Error> {
+    let mut tx = database.begin().await?;
+    let suspended = Token::suspend_idle(&mut *tx, idle_days).await?;
+    for uuid in &suspended {
+        let _ = AuditLogEntry::log_system_action(
+            &mut *tx,
+            AuditLogAction::TokenSuspend,
+            json!({ "token": uuid, "reason": "idle", "idle_days": idle_days }),
+        )
+        .await?;
+    }
+    tx.commit().await?;
+
+    for uuid in suspended {
+        info!("suspended token=`{}` after {} idle days", uuid, idle_days);
+    }
+
+    Ok(())
+}
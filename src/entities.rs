@@ -1,9 +1,41 @@
+mod access_request;
 mod audit_log_entry;
+mod break_glass;
+mod certificate_authority;
 mod client_addr;
+mod database_connection;
+mod jwt_issuer;
+mod lease;
 mod secret;
+mod secret_bundle;
+mod secret_format;
+mod secret_generator;
+mod secret_template;
+mod signed_request;
+mod ssh_certificate_authority;
+mod stale_token_report;
 mod token;
+mod token_usage;
+mod transit_key;
 
+pub use access_request::AccessRequest;
 pub use audit_log_entry::{AuditLogAction, AuditLogEntry};
-pub use client_addr::ExtractClientAddr;
-pub use secret::Secret;
-pub use token::ExtractValidToken;
+pub use break_glass::{BreakGlassReads, ExtractJustification};
+pub use certificate_authority::{CertificateAuthority, CertificateSigner, RevocationState};
+pub use client_addr::{ClientAddr, ExtractClientAddr};
+pub use database_connection::{DatabaseConnection, DatabaseLease};
+pub use jwt_issuer::{JwtIssuer, unverified_issuer};
+pub use lease::{Lease, LeaseKind};
+pub use secret::{Secret, SecretKind, SecretSummary};
+pub use secret_bundle::SecretBundle;
+pub use secret_format::{ExtractSecretFormat, SecretFormat};
+pub use secret_generator::GeneratorSpec;
+pub use secret_template::{SecretReference, SecretTemplate};
+pub use signed_request::{ExtractSignedToken, delete_expired_nonces, digest_signed_body};
+pub use ssh_certificate_authority::{SshCertificateAuthority, SshCertificateType};
+pub use stale_token_report::StaleTokenReport;
+pub use token::{ExtractValidToken, SecretGrant, Token};
+pub use token_usage::{DailyTokenUsage, MAX_USAGE_DAYS, TokenUsage};
+pub use transit_key::{
+    TransitKey, TransitKeySummary, TransitPermissions, decode_base64, parse_versioned,
+};
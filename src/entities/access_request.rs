@@ -0,0 +1,188 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::{PgConnection, PgExecutor};
+use uuid::Uuid;
+
+/// How long a request can wait for an approval, in seconds.
+const PENDING_REQUEST_TTL: i64 = 24 * 60 * 60;
+
+/// A token's request to read a secret that requires approval. Once another
+/// token with approver rights approved it, the requesting token can read the
+/// secret once, until `read_until`.
+#[derive(Debug, Serialize)]
+pub struct AccessRequest {
+    pub uuid: Uuid,
+    pub secret: Uuid,
+    pub token: Uuid,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    pub approved_by: Option<Uuid>,
+    pub approved_at: Option<DateTime<Utc>>,
+    pub read_until: Option<DateTime<Utc>>,
+}
+
+impl AccessRequest {
+    /// Stores a new pending request, which expires if it isn't approved in
+    /// time.
+    pub async fn create<'e>(
+        db: impl PgExecutor<'e>,
+        secret: Uuid,
+        token: Uuid,
+    ) -> Result<Self, sqlx::Error> {
+        sqlx::query_as!(
+            Self,
+            r#"insert into access_requests (secret, token, expires_at)
+            values ($1, $2, now() + make_interval(secs => $3))
+            returning
+              uuid, secret, token, created_at, expires_at,
+              approved_by, approved_at, read_until"#,
+            secret,
+            token,
+            PENDING_REQUEST_TTL as f64
+        )
+        .fetch_one(db)
+        .await
+    }
+
+    /// Tries to find a token's request for a secret that is still waiting for
+    /// an approval. If there is none, it will result with None().
+    pub async fn try_find_pending<'e>(
+        db: impl PgExecutor<'e>,
+        token: Uuid,
+        secret: Uuid,
+    ) -> Result<Option<Self>, sqlx::Error> {
+        sqlx::query_as!(
+            Self,
+            r#"select
+              uuid, secret, token, created_at, expires_at,
+              approved_by, approved_at, read_until
+            from access_requests
+            where token = $1 and secret = $2 and approved_at is null and expires_at > now()
+            order by created_at desc limit 1"#,
+            token,
+            secret
+        )
+        .fetch_optional(db)
+        .await
+    }
+
+    /// Tries to find a token's approved request for a secret that hasn't been
+    /// used for a read yet, and locks it until the end of the transaction. If
+    /// there is none, or the read window is over, it will result with None().
+    pub async fn try_find_readable_for_update(
+        conn: &mut PgConnection,
+        token: Uuid,
+        secret: Uuid,
+    ) -> Result<Option<Self>, sqlx::Error> {
+        sqlx::query_as!(
+            Self,
+            r#"select
+              uuid, secret, token, created_at, expires_at,
+              approved_by, approved_at, read_until
+            from access_requests
+            where token = $1 and secret = $2 and read_at is null and read_until > now()
+            order by approved_at limit 1 for update"#,
+            token,
+            secret
+        )
+        .fetch_optional(conn)
+        .await
+    }
+
+    /// Tries to find a request that is still waiting for an approval, and
+    /// locks it until the end of the transaction. Expired and approved requests
+    /// result in None().
+    pub async fn try_find_pending_for_update(
+        conn: &mut PgConnection,
+        uuid: Uuid,
+    ) -> Result<Option<Self>, sqlx::Error> {
+        sqlx::query_as!(
+            Self,
+            r#"select
+              uuid, secret, token, created_at, expires_at,
+              approved_by, approved_at, read_until
+            from access_requests
+            where uuid = $1 and approved_at is null and expires_at > now()
+            for update"#,
+            uuid
+        )
+        .fetch_optional(conn)
+        .await
+    }
+
+    /// Lists all requests that are still waiting for an approval, oldest
+    /// first.
+    pub async fn list_pending<'e>(db: impl PgExecutor<'e>) -> Result<Vec<Self>, sqlx::Error> {
+        sqlx::query_as!(
+            Self,
+            r#"select
+              uuid, secret, token, created_at, expires_at,
+              approved_by, approved_at, read_until
+            from access_requests
+            where approved_at is null and expires_at > now()
+            order by created_at"#
+        )
+        .fetch_all(db)
+        .await
+    }
+
+    /// Checks if a token is the requesting token, or one of its ancestors or
+    /// descendants. Those can't approve the request, as that would allow a
+    /// token to approve its own requests via a child token.
+    pub async fn is_related_to<'e>(
+        &self,
+        db: impl PgExecutor<'e>,
+        token: Uuid,
+    ) -> Result<bool, sqlx::Error> {
+        sqlx::query_scalar!(
+            r#"select $1::uuid = $2::uuid or exists(
+              select 1 from token_ancestors
+              where (token = $1 and ancestor = $2) or (token = $2 and ancestor = $1)
+            ) as "related!""#,
+            self.token,
+            token
+        )
+        .fetch_one(db)
+        .await
+    }
+
+    /// Approves the request, which allows a single read of the secret within
+    /// the next `window_seconds`. The request should be locked by the caller's
+    /// transaction.
+    pub async fn approve(
+        &mut self,
+        conn: &mut PgConnection,
+        approver: Uuid,
+        window_seconds: i64,
+    ) -> Result<(), sqlx::Error> {
+        let row = sqlx::query!(
+            r#"update access_requests
+            set approved_by = $2, approved_at = now(), read_until = now() + make_interval(secs => $3)
+            where uuid = $1
+            returning approved_at, read_until"#,
+            self.uuid,
+            approver,
+            window_seconds as f64
+        )
+        .fetch_one(conn)
+        .await?;
+
+        self.approved_by = Some(approver);
+        self.approved_at = row.approved_at;
+        self.read_until = row.read_until;
+        Ok(())
+    }
+
+    /// Uses up the request's single read. The request should be locked by the
+    /// caller's transaction.
+    pub async fn mark_read(&self, conn: &mut PgConnection) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            "update access_requests set read_at = now() where uuid = $1",
+            self.uuid
+        )
+        .execute(conn)
+        .await?;
+
+        Ok(())
+    }
+}
@@ -8,6 +8,24 @@ use uuid::Uuid;
 pub enum AuditLogAction {
     SecretRead,
     SecretWrite,
+    TransitEncrypt,
+    TransitDecrypt,
+    TransitRewrap,
+    TransitSign,
+    TransitVerify,
+    CertificateIssue,
+    SshCertificateSign,
+    DatabaseCredentialsCreate,
+    DatabaseCredentialsRevoke,
+    CertificateRevoke,
+    LeaseRenew,
+    ClientLockout,
+    TokenSuspend,
+    TokenUnsuspend,
+    AccessDenied,
+    AccessRequest,
+    AccessApprove,
+    BreakGlassRead,
 }
 
 /// An individual entry in the Audit Log. Note that the struct is completely
@@ -26,16 +44,9 @@ impl AuditLogEntry {
         token: Uuid,
         secret: Uuid,
     ) -> Result<PgQueryResult, sqlx::Error> {
-        let client_addr = client_addr.to_canonical();
-        let ip_net = match client_addr {
-            IpAddr::V4(_) => IpNetwork::new(client_addr, 32),
-            IpAddr::V6(_) => IpNetwork::new(client_addr, 128),
-        }
-        .expect("IP address provided here should always be valid");
-
         sqlx::query!(
             "insert into audit_log (client_addr, action, token, secret) values ($1, $2, $3, $4)",
-            ip_net,
+            canonical_network(client_addr),
             action as AuditLogAction,
             token,
             secret
@@ -43,4 +54,136 @@ impl AuditLogEntry {
         .execute(db)
         .await
     }
+
+    /// Stores an action in the audit log, just like [Self::log_action], but
+    /// with additional details about the action.
+    pub async fn log_action_with_details<'e>(
+        db: impl PgExecutor<'e>,
+        client_addr: IpAddr,
+        action: AuditLogAction,
+        token: Uuid,
+        secret: Uuid,
+        details: serde_json::Value,
+    ) -> Result<PgQueryResult, sqlx::Error> {
+        sqlx::query!(
+            "insert into audit_log (client_addr, action, token, secret, details) values ($1, $2, $3, $4, $5)",
+            canonical_network(client_addr),
+            action as AuditLogAction,
+            token,
+            secret,
+            details
+        )
+        .execute(db)
+        .await
+    }
+
+    /// Stores an action in the audit log with a high severity, so it stands
+    /// out, like reads of break-glass tokens. Otherwise, this works just like
+    /// [Self::log_action_with_details].
+    pub async fn log_high_severity_action<'e>(
+        db: impl PgExecutor<'e>,
+        client_addr: IpAddr,
+        action: AuditLogAction,
+        token: Uuid,
+        secret: Uuid,
+        details: serde_json::Value,
+    ) -> Result<PgQueryResult, sqlx::Error> {
+        sqlx::query!(
+            "insert into audit_log (client_addr, action, token, secret, details, severity) values ($1, $2, $3, $4, $5, 'high')",
+            canonical_network(client_addr),
+            action as AuditLogAction,
+            token,
+            secret,
+            details
+        )
+        .execute(db)
+        .await
+    }
+
+    /// Stores an action on a transit key in the audit log. This works just
+    /// like [Self::log_action], except that the action refers to a key instead
+    /// of a secret.
+    pub async fn log_transit_action<'e>(
+        db: impl PgExecutor<'e>,
+        client_addr: IpAddr,
+        action: AuditLogAction,
+        token: Uuid,
+        transit_key: Uuid,
+    ) -> Result<PgQueryResult, sqlx::Error> {
+        sqlx::query!(
+            "insert into audit_log (client_addr, action, token, transit_key) values ($1, $2, $3, $4)",
+            canonical_network(client_addr),
+            action as AuditLogAction,
+            token,
+            transit_key
+        )
+        .execute(db)
+        .await
+    }
+
+    /// Stores an action that a token took on something other than a secret or
+    /// a transit key, like suspending another token. What the action was about
+    /// goes into the details.
+    pub async fn log_token_action<'e>(
+        db: impl PgExecutor<'e>,
+        client_addr: IpAddr,
+        action: AuditLogAction,
+        token: Uuid,
+        details: serde_json::Value,
+    ) -> Result<PgQueryResult, sqlx::Error> {
+        sqlx::query!(
+            "insert into audit_log (client_addr, action, token, details) values ($1, $2, $3, $4)",
+            canonical_network(client_addr),
+            action as AuditLogAction,
+            token,
+            details
+        )
+        .execute(db)
+        .await
+    }
+
+    /// Stores an action that concerns a client address rather than a token or
+    /// a secret, like a lockout after too many invalid tokens.
+    pub async fn log_client_action<'e>(
+        db: impl PgExecutor<'e>,
+        client_addr: IpAddr,
+        action: AuditLogAction,
+        details: serde_json::Value,
+    ) -> Result<PgQueryResult, sqlx::Error> {
+        sqlx::query!(
+            "insert into audit_log (client_addr, action, details) values ($1, $2, $3)",
+            canonical_network(client_addr),
+            action as AuditLogAction,
+            details
+        )
+        .execute(db)
+        .await
+    }
+
+    /// Stores an action that vssv took on its own, like suspending an idle
+    /// token. These entries have neither a client address nor a token.
+    pub async fn log_system_action<'e>(
+        db: impl PgExecutor<'e>,
+        action: AuditLogAction,
+        details: serde_json::Value,
+    ) -> Result<PgQueryResult, sqlx::Error> {
+        sqlx::query!(
+            "insert into audit_log (action, details) values ($1, $2)",
+            action as AuditLogAction,
+            details
+        )
+        .execute(db)
+        .await
+    }
+}
+
+/// Turns an IP address into a single-host network, as that's what the inet
+/// column expects.
+fn canonical_network(client_addr: IpAddr) -> IpNetwork {
+    let client_addr = client_addr.to_canonical();
+    match client_addr {
+        IpAddr::V4(_) => IpNetwork::new(client_addr, 32),
+        IpAddr::V6(_) => IpNetwork::new(client_addr, 128),
+    }
+    .expect("IP address provided here should always be valid")
 }
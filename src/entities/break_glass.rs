@@ -0,0 +1,118 @@
+use std::net::IpAddr;
+
+use axum::{extract::FromRequestParts, http::request::Parts};
+use chrono::Utc;
+use reqwest::Url;
+use serde_json::json;
+use sqlx::PgExecutor;
+use uuid::Uuid;
+
+use crate::{
+    components::notifier,
+    entities::{AuditLogAction, AuditLogEntry, Token},
+    errors::ResponseError,
+};
+
+/// The header break-glass tokens have to send their justification in.
+const JUSTIFICATION_HEADER: &str = "x-break-glass-justification";
+
+/// Justifications have to be at least this long, so "x" doesn't count.
+const MIN_JUSTIFICATION_LENGTH: usize = 10;
+
+const MAX_JUSTIFICATION_LENGTH: usize = 1000;
+
+/// The justification for a break-glass read, if the request has one.
+#[derive(Debug)]
+pub struct ExtractJustification(pub Option<String>);
+
+impl<S> FromRequestParts<S> for ExtractJustification
+where
+    S: Send + Sync,
+{
+    type Rejection = ResponseError;
+
+    /// Reads the justification header. Requests without the header extract to
+    /// None(), but if the header is there, it has to be readable and of a
+    /// reasonable length, or the request is rejected with a 400.
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let Some(header) = parts.headers.get(JUSTIFICATION_HEADER) else {
+            return Ok(Self(None));
+        };
+
+        let justification = header
+            .to_str()
+            .map_err(|_| ResponseError::InvalidJustification())?
+            .trim();
+        if !(MIN_JUSTIFICATION_LENGTH..=MAX_JUSTIFICATION_LENGTH)
+            .contains(&justification.chars().count())
+        {
+            return Err(ResponseError::InvalidJustification());
+        }
+
+        Ok(Self(Some(justification.to_string())))
+    }
+}
+
+/// The break-glass reads of a single request. Every read is stored in the
+/// audit log right away, as part of the request's transaction, but webhooks
+/// are only notified via [Self::notify], once the secrets have actually been
+/// read.
+#[derive(Debug)]
+pub struct BreakGlassReads<'a> {
+    justification: &'a str,
+    secrets: Vec<Uuid>,
+}
+
+impl<'a> BreakGlassReads<'a> {
+    /// Starts a break-glass request, if the token is allowed to break the
+    /// glass and gave a justification. Otherwise, this returns None(), and the
+    /// request is subject to the usual permission checks.
+    pub fn start(token: &Token, justification: Option<&'a str>) -> Option<Self> {
+        justification
+            .filter(|_| token.break_glass)
+            .map(|justification| Self {
+                justification,
+                secrets: Vec::new(),
+            })
+    }
+
+    /// Stores a break-glass read in the audit log with a high severity,
+    /// including the justification.
+    pub async fn log<'e>(
+        &mut self,
+        db: impl PgExecutor<'e>,
+        client_addr: IpAddr,
+        token: Uuid,
+        secret: Uuid,
+    ) -> Result<(), sqlx::Error> {
+        let _ = AuditLogEntry::log_high_severity_action(
+            db,
+            client_addr,
+            AuditLogAction::BreakGlassRead,
+            token,
+            secret,
+            json!({ "justification": self.justification }),
+        )
+        .await?;
+        self.secrets.push(secret);
+
+        Ok(())
+    }
+
+    /// Notifies all webhooks about every secret that was read.
+    pub fn notify(self, webhooks: &[Url], client_addr: IpAddr, token: Uuid) {
+        for secret in self.secrets {
+            notifier::notify(
+                webhooks,
+                json!({
+                    "event": "break_glass_read",
+                    "token": token,
+                    "secret": secret,
+                    "client_addr": client_addr,
+                    "justification": self.justification,
+                    "timestamp": Utc::now(),
+                }),
+            );
+        }
+    }
+}
@@ -0,0 +1,443 @@
+use anyhow::Context;
+use chrono::{DateTime, Duration, Utc};
+use rand::{RngCore, rngs::OsRng};
+use rcgen::{
+    CertificateParams, CertificateRevocationListParams, DnType, ExtendedKeyUsagePurpose, IsCa,
+    Issuer, KeyIdMethod, KeyPair, KeyUsagePurpose, RevokedCertParams, SerialNumber,
+};
+use sqlx::PgExecutor;
+use time::OffsetDateTime;
+use uuid::Uuid;
+use x509_parser::{extensions::ParsedExtension, pem::parse_x509_pem};
+
+use crate::{
+    entities::{Secret, SecretKind},
+    errors::ResponseError,
+};
+
+const SERIAL_NUMBER_LENGTH: usize = 16;
+const CRL_VALIDITY: Duration = Duration::hours(24);
+
+/// A certificate authority that can issue short-lived leaf certificates. The
+/// CA's certificate and key are stored in a structured secret.
+#[derive(Debug)]
+pub struct CertificateAuthority {
+    pub uuid: Uuid,
+    pub secret: Uuid,
+    pub max_ttl_seconds: i64,
+}
+
+/// A summary of a CA's revoked certificates that haven't expired yet. If this
+/// changes, so does the CA's CRL.
+#[derive(Debug, PartialEq, Eq)]
+pub struct RevocationState {
+    pub revoked: i64,
+    pub last_revoked_at: Option<DateTime<Utc>>,
+}
+
+/// A freshly issued leaf certificate, including its private key.
+#[derive(Debug)]
+pub struct IssuedCertificate {
+    pub serial_number: String,
+    pub certificate: String,
+    pub private_key: String,
+    pub not_before: DateTime<Utc>,
+    pub not_after: DateTime<Utc>,
+}
+
+/// The CA material loaded from the CA's secret, ready to sign things.
+pub struct CertificateSigner {
+    pub certificate: String,
+    issuer: Issuer<'static, KeyPair>,
+    key_identifier: KeyIdMethod,
+}
+
+impl CertificateAuthority {
+    /// Tries to find a CA by its name. If nothing is found, it will result
+    /// with None().
+    pub async fn try_find_by_name<'e>(
+        db: impl PgExecutor<'e>,
+        name: &str,
+    ) -> Result<Option<Self>, sqlx::Error> {
+        sqlx::query_as!(
+            Self,
+            r#"select uuid, secret, extract(epoch from max_ttl)::bigint as "max_ttl_seconds!"
+            from certificate_authorities where name = $1"#,
+            name
+        )
+        .fetch_optional(db)
+        .await
+    }
+
+    /// Returns the longest TTL (in seconds) a token is allowed to request for a
+    /// certificate containing all the given names. Only policies that allow
+    /// every single name are considered, and the result is capped by the CA's
+    /// own maximum. Returns None() if no policy allows the names, which is
+    /// never the case for superuser tokens.
+    pub async fn max_ttl_for<'e>(
+        &self,
+        db: impl PgExecutor<'e>,
+        token_uuid: Uuid,
+        superuser: bool,
+        names: &[String],
+    ) -> Result<Option<i64>, sqlx::Error> {
+        if superuser {
+            return Ok(Some(self.max_ttl_seconds));
+        }
+
+        let policies = sqlx::query!(
+            r#"select p.allowed_names, p.allow_wildcards, extract(epoch from p.max_ttl)::bigint as "max_ttl_seconds!"
+            from token_roles tr
+            join role_certificate_policies p on p.role = tr.role
+            where tr.token = $1 and p.authority = $2"#,
+            token_uuid,
+            self.uuid
+        )
+        .fetch_all(db)
+        .await?;
+
+        Ok(policies
+            .into_iter()
+            .filter(|policy| {
+                names.iter().all(|name| {
+                    policy
+                        .allowed_names
+                        .iter()
+                        .any(|pattern| name_matches(pattern, name, policy.allow_wildcards))
+                })
+            })
+            .map(|policy| policy.max_ttl_seconds.min(self.max_ttl_seconds))
+            .max())
+    }
+
+    /// Stores an issued certificate in the serial number index, along with the
+    /// lease that tracks it.
+    pub async fn record_issued<'e>(
+        &self,
+        db: impl PgExecutor<'e>,
+        lease: Uuid,
+        token_uuid: Uuid,
+        names: &[String],
+        issued: &IssuedCertificate,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query!(
+            "insert into issued_certificates (serial_number, authority, lease, token, names, not_before, not_after) values ($1, $2, $3, $4, $5, $6, $7)",
+            issued.serial_number,
+            self.uuid,
+            lease,
+            token_uuid,
+            names,
+            issued.not_before,
+            issued.not_after
+        )
+        .execute(db)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Summarizes the revoked certificates that would end up in the CRL. This
+    /// is a lot cheaper than building the CRL itself.
+    pub async fn revocation_state<'e>(
+        &self,
+        db: impl PgExecutor<'e>,
+    ) -> Result<RevocationState, sqlx::Error> {
+        sqlx::query_as!(
+            RevocationState,
+            r#"select count(*) as "revoked!", max(revoked_at) as last_revoked_at
+            from issued_certificates
+            where authority = $1 and revoked_at is not null and not_after > now()"#,
+            self.uuid
+        )
+        .fetch_one(db)
+        .await
+    }
+
+    /// Builds a DER-encoded CRL containing all revoked certificates that are
+    /// not expired yet.
+    pub async fn revocation_list<'e>(
+        &self,
+        db: impl PgExecutor<'e>,
+        signer: &CertificateSigner,
+    ) -> Result<Vec<u8>, ResponseError> {
+        let revoked = sqlx::query!(
+            r#"select serial_number, revoked_at as "revoked_at!"
+            from issued_certificates
+            where authority = $1 and revoked_at is not null and not_after > now()
+            order by revoked_at"#,
+            self.uuid
+        )
+        .fetch_all(db)
+        .await?;
+
+        let now = Utc::now();
+        let params = CertificateRevocationListParams {
+            this_update: to_offset_date_time(now)?,
+            next_update: to_offset_date_time(now + CRL_VALIDITY)?,
+            crl_number: SerialNumber::from(now.timestamp() as u64),
+            issuing_distribution_point: None,
+            revoked_certs: revoked
+                .into_iter()
+                .map(|entry| {
+                    Ok(RevokedCertParams {
+                        serial_number: SerialNumber::from_slice(
+                            &decode_hex(&entry.serial_number)
+                                .context("invalid serial number in index")?,
+                        ),
+                        revocation_time: to_offset_date_time(entry.revoked_at)?,
+                        reason_code: None,
+                        invalidity_date: None,
+                    })
+                })
+                .collect::<anyhow::Result<_>>()?,
+            key_identifier_method: signer.key_identifier.clone(),
+        };
+
+        Ok(params
+            .signed_by(&signer.issuer)
+            .context("could not sign CRL")?
+            .der()
+            .to_vec())
+    }
+}
+
+impl CertificateSigner {
+    /// Loads the CA's certificate and private key from its secret. The secret
+    /// has to be a structured secret with PEM-encoded `certificate` and
+    /// `private_key` fields.
+    pub fn load(secret: &Secret) -> Result<Self, ResponseError> {
+        if secret.kind != SecretKind::Structured {
+            return Err(anyhow::anyhow!("CA secret `{}` is not structured", secret.uuid).into());
+        }
+
+        let fields = secret.fields();
+        let (Some(certificate), Some(private_key)) =
+            (fields.get("certificate"), fields.get("private_key"))
+        else {
+            return Err(anyhow::anyhow!("CA secret `{}` is missing fields", secret.uuid).into());
+        };
+
+        let key = KeyPair::from_pem(private_key).context("invalid CA private key")?;
+        let issuer =
+            Issuer::from_ca_cert_pem(certificate, key).context("invalid CA certificate")?;
+
+        // rcgen doesn't expose the CA's key identifier, but CRLs have to use
+        // the same one, so we have to extract it ourselves.
+        let (_, pem) = parse_x509_pem(certificate.as_bytes()).context("invalid CA certificate")?;
+        let ca_certificate = pem.parse_x509().context("invalid CA certificate")?;
+        let key_identifier = ca_certificate
+            .iter_extensions()
+            .find_map(|extension| match extension.parsed_extension() {
+                ParsedExtension::SubjectKeyIdentifier(key_id) => {
+                    Some(KeyIdMethod::PreSpecified(key_id.0.to_vec()))
+                }
+                _ => None,
+            })
+            .unwrap_or(KeyIdMethod::Sha256);
+
+        Ok(Self {
+            certificate: certificate.clone(),
+            issuer,
+            key_identifier,
+        })
+    }
+
+    /// Issues a new leaf certificate with a freshly generated ECDSA P-256 key.
+    /// The first name is used as the common name, and all names are added as
+    /// subject alternative names. The names and TTL have to be checked against
+    /// the policies beforehand.
+    pub fn issue(
+        &self,
+        names: &[String],
+        ttl_seconds: i64,
+    ) -> Result<IssuedCertificate, ResponseError> {
+        let mut params = CertificateParams::new(names)
+            .map_err(|e| ResponseError::InvalidCertificateRequest(e.to_string()))?;
+        params
+            .distinguished_name
+            .push(DnType::CommonName, names[0].clone());
+
+        let not_before = Utc::now();
+        let not_after = not_before + Duration::seconds(ttl_seconds);
+        params.not_before = to_offset_date_time(not_before)?;
+        params.not_after = to_offset_date_time(not_after)?;
+
+        let mut serial_number = [0; SERIAL_NUMBER_LENGTH];
+        OsRng.fill_bytes(&mut serial_number);
+        // Keep the serial number positive and without leading zeroes, so it
+        // is encoded with the exact length everywhere.
+        serial_number[0] = (serial_number[0] & 0x7f) | 0x40;
+        params.serial_number = Some(SerialNumber::from_slice(&serial_number));
+
+        params.is_ca = IsCa::ExplicitNoCa;
+        params.key_usages = vec![KeyUsagePurpose::DigitalSignature];
+        params.extended_key_usages = vec![
+            ExtendedKeyUsagePurpose::ServerAuth,
+            ExtendedKeyUsagePurpose::ClientAuth,
+        ];
+        params.use_authority_key_identifier_extension = true;
+
+        let key = KeyPair::generate().context("could not generate key")?;
+        let certificate = params
+            .signed_by(&key, &self.issuer)
+            .context("could not sign certificate")?;
+
+        Ok(IssuedCertificate {
+            serial_number: serial_number.iter().map(|b| format!("{:02x}", b)).collect(),
+            certificate: certificate.pem(),
+            private_key: key.serialize_pem(),
+            not_before,
+            not_after,
+        })
+    }
+}
+
+/// Checks if a name is allowed by a policy pattern. `*.example.com` matches all
+/// names below `example.com`, at any depth, but not `example.com` itself.
+/// Everything else has to match exactly, ignoring case. Requested names that
+/// contain a `*` are rejected, unless `allow_wildcards` is set. Then, names
+/// like `*.api.example.com` are allowed, if they are covered by the pattern.
+fn name_matches(pattern: &str, name: &str, allow_wildcards: bool) -> bool {
+    let pattern = pattern.to_lowercase();
+    let name = name.to_lowercase();
+
+    if name.contains('*') {
+        let valid_wildcard = name
+            .strip_prefix("*.")
+            .is_some_and(|rest| !rest.is_empty() && !rest.contains('*'));
+        if !allow_wildcards || !valid_wildcard {
+            return false;
+        }
+    }
+
+    match pattern.strip_prefix('*') {
+        Some(suffix) if suffix.starts_with('.') => {
+            name.len() > suffix.len() && name.ends_with(suffix)
+        }
+        _ => pattern == name,
+    }
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+fn to_offset_date_time(date_time: DateTime<Utc>) -> anyhow::Result<OffsetDateTime> {
+    Ok(OffsetDateTime::from_unix_timestamp(date_time.timestamp())?)
+}
+
+#[cfg(test)]
+mod tests {
+    use rcgen::BasicConstraints;
+    use sqlx::types::Json as SqlJson;
+    use x509_parser::extensions::GeneralName;
+
+    use super::*;
+
+    fn ca_secret(kind: SecretKind) -> Secret {
+        let key = KeyPair::generate().unwrap();
+        let mut params = CertificateParams::new(vec![]).unwrap();
+        params
+            .distinguished_name
+            .push(DnType::CommonName, "test CA");
+        params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        params.key_usages = vec![KeyUsagePurpose::KeyCertSign, KeyUsagePurpose::CrlSign];
+        let certificate = params.self_signed(&key).unwrap();
+
+        Secret {
+            uuid: Uuid::nil(),
+            kind,
+            file_name: None,
+            contents: None,
+            fields: Some(SqlJson(
+                [
+                    ("certificate".to_string(), certificate.pem()),
+                    ("private_key".to_string(), key.serialize_pem()),
+                ]
+                .into(),
+            )),
+            requires_approval: false,
+        }
+    }
+
+    #[test]
+    fn matches_exact_names() {
+        assert!(name_matches("api.example.com", "api.example.com", false));
+        assert!(name_matches("API.example.com", "api.EXAMPLE.com", false));
+        assert!(!name_matches("api.example.com", "web.example.com", false));
+        assert!(!name_matches("api.example.com", "x.api.example.com", false));
+    }
+
+    #[test]
+    fn matches_wildcards() {
+        assert!(name_matches("*.svc.internal", "db.svc.internal", false));
+        assert!(name_matches("*.svc.internal", "a.b.svc.internal", false));
+        assert!(!name_matches("*.svc.internal", "svc.internal", false));
+        assert!(!name_matches("*.svc.internal", ".svc.internal", false));
+        assert!(!name_matches("*.svc.internal", "evilsvc.internal", false));
+        assert!(!name_matches("*svc.internal", "evilsvc.internal", false));
+    }
+
+    #[test]
+    fn rejects_wildcard_names_unless_allowed() {
+        assert!(!name_matches("*.svc.internal", "*.svc.internal", false));
+        assert!(!name_matches("*.svc.internal", "*.db.svc.internal", false));
+        assert!(!name_matches("*.svc.internal", "db*.svc.internal", false));
+        assert!(!name_matches("*.svc.internal", "*", false));
+
+        assert!(name_matches("*.svc.internal", "*.svc.internal", true));
+        assert!(name_matches("*.svc.internal", "*.db.svc.internal", true));
+        assert!(name_matches("*.db.svc.internal", "*.db.svc.internal", true));
+        assert!(!name_matches("*.db.svc.internal", "*.svc.internal", true));
+        assert!(!name_matches("*.svc.internal", "db*.svc.internal", true));
+        assert!(!name_matches("*.svc.internal", "*.*.svc.internal", true));
+        assert!(!name_matches("db.svc.internal", "*.svc.internal", true));
+    }
+
+    #[test]
+    fn decodes_hex() {
+        assert_eq!(decode_hex("00ff7a"), Some(vec![0x00, 0xff, 0x7a]));
+        assert_eq!(decode_hex(""), Some(vec![]));
+        assert_eq!(decode_hex("abc"), None);
+        assert_eq!(decode_hex("zz"), None);
+    }
+
+    #[test]
+    fn rejects_unstructured_ca_secrets() {
+        assert!(CertificateSigner::load(&ca_secret(SecretKind::Blob)).is_err());
+    }
+
+    #[test]
+    fn issues_leaf_certificates() {
+        let signer = CertificateSigner::load(&ca_secret(SecretKind::Structured)).unwrap();
+        let names = vec!["db.svc.internal".to_string(), "db".to_string()];
+        let issued = signer.issue(&names, 3600).unwrap();
+        assert_eq!(issued.serial_number.len(), SERIAL_NUMBER_LENGTH * 2);
+        assert_eq!((issued.not_after - issued.not_before).num_seconds(), 3600);
+
+        let (_, pem) = parse_x509_pem(issued.certificate.as_bytes()).unwrap();
+        let certificate = pem.parse_x509().unwrap();
+        assert_eq!(
+            certificate.raw_serial_as_string().replace(':', ""),
+            issued.serial_number
+        );
+        assert!(!certificate.is_ca());
+        assert_eq!(certificate.issuer().to_string(), "CN=test CA");
+        assert_eq!(certificate.subject().to_string(), "CN=db.svc.internal");
+
+        let alt_names = certificate.subject_alternative_name().unwrap().unwrap();
+        let alt_names: Vec<_> = alt_names
+            .value
+            .general_names
+            .iter()
+            .map(|name| match name {
+                GeneralName::DNSName(name) => *name,
+                _ => panic!("unexpected alt name {:?}", name),
+            })
+            .collect();
+        assert_eq!(alt_names, ["db.svc.internal", "db"]);
+    }
+}
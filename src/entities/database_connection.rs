@@ -0,0 +1,230 @@
+use anyhow::Context;
+use chrono::{DateTime, Duration, Utc};
+use rand::{Rng, distributions::Alphanumeric, rngs::OsRng};
+use sqlx::{Connection, Executor, PgConnection, PgExecutor};
+use uuid::Uuid;
+
+use crate::{entities::Secret, errors::ResponseError};
+
+const PASSWORD_LENGTH: usize = 32;
+const USERNAME_SUFFIX_LENGTH: usize = 16;
+
+/// A PostgreSQL database that vssv creates short-lived roles in. The connection
+/// URL is stored in a secret.
+#[derive(Debug)]
+pub struct DatabaseConnection {
+    pub uuid: Uuid,
+    pub name: String,
+    pub secret: Uuid,
+    pub creation_statements: String,
+    pub revocation_statements: String,
+    pub renewal_statements: String,
+    pub default_ttl_seconds: i64,
+    pub max_ttl_seconds: i64,
+}
+
+/// A set of freshly created credentials.
+#[derive(Debug)]
+pub struct DatabaseCredentials {
+    pub username: String,
+    pub password: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// The role a [crate::entities::Lease] was created for. Once the lease expires
+/// or gets revoked, the role is dropped.
+#[derive(Debug)]
+pub struct DatabaseLease {
+    pub connection: Uuid,
+    pub username: String,
+}
+
+impl DatabaseConnection {
+    /// Tries to find a database connection by its name. If nothing is found, it
+    /// will result with None().
+    pub async fn try_find_by_name<'e>(
+        db: impl PgExecutor<'e>,
+        name: &str,
+    ) -> Result<Option<Self>, sqlx::Error> {
+        sqlx::query_as!(
+            Self,
+            r#"select
+              uuid, name, secret, creation_statements, revocation_statements, renewal_statements,
+              extract(epoch from default_ttl)::bigint as "default_ttl_seconds!",
+              extract(epoch from max_ttl)::bigint as "max_ttl_seconds!"
+            from database_connections where name = $1"#,
+            name
+        )
+        .fetch_optional(db)
+        .await
+    }
+
+    /// Finds a database connection by its UUID.
+    pub async fn find<'e>(db: impl PgExecutor<'e>, uuid: Uuid) -> Result<Self, sqlx::Error> {
+        sqlx::query_as!(
+            Self,
+            r#"select
+              uuid, name, secret, creation_statements, revocation_statements, renewal_statements,
+              extract(epoch from default_ttl)::bigint as "default_ttl_seconds!",
+              extract(epoch from max_ttl)::bigint as "max_ttl_seconds!"
+            from database_connections where uuid = $1"#,
+            uuid
+        )
+        .fetch_one(db)
+        .await
+    }
+
+    /// Returns the longest TTL (in seconds) a token can request credentials
+    /// for, based on the database policies of the token's roles, and capped by
+    /// the connection's own maximum. Returns None() if the token isn't allowed
+    /// to request credentials at all, which is never the case for superuser
+    /// tokens.
+    pub async fn max_ttl_for<'e>(
+        &self,
+        db: impl PgExecutor<'e>,
+        token_uuid: Uuid,
+        superuser: bool,
+    ) -> Result<Option<i64>, sqlx::Error> {
+        if superuser {
+            return Ok(Some(self.max_ttl_seconds));
+        }
+
+        let max_ttl = sqlx::query_scalar!(
+            r#"select max(extract(epoch from p.max_ttl))::bigint
+            from token_roles tr
+            join role_database_policies p on p.role = tr.role
+            where tr.token = $1 and p.connection = $2"#,
+            token_uuid,
+            self.uuid
+        )
+        .fetch_one(db)
+        .await?;
+
+        Ok(max_ttl.map(|max_ttl| max_ttl.min(self.max_ttl_seconds)))
+    }
+
+    /// Creates a new role with a random name and password in the target
+    /// database, using the connection's creation statements. The role is valid
+    /// for the given TTL.
+    pub async fn create_credentials(
+        &self,
+        secret: &Secret,
+        ttl_seconds: i64,
+    ) -> Result<DatabaseCredentials, ResponseError> {
+        let suffix: String = random_string(USERNAME_SUFFIX_LENGTH).to_lowercase();
+        let credentials = DatabaseCredentials {
+            username: format!("vssv_{}", suffix),
+            password: random_string(PASSWORD_LENGTH),
+            expires_at: Utc::now() + Duration::seconds(ttl_seconds),
+        };
+
+        self.execute(
+            secret,
+            &self.creation_statements,
+            &credentials.username,
+            &credentials.password,
+            credentials.expires_at,
+        )
+        .await?;
+
+        Ok(credentials)
+    }
+
+    /// Extends a role's validity, using the connection's renewal statements.
+    pub async fn renew_credentials(
+        &self,
+        secret: &Secret,
+        username: &str,
+        expires_at: DateTime<Utc>,
+    ) -> Result<(), ResponseError> {
+        self.execute(secret, &self.renewal_statements, username, "", expires_at)
+            .await
+    }
+
+    /// Drops a role, using the connection's revocation statements.
+    pub async fn revoke_credentials(
+        &self,
+        secret: &Secret,
+        username: &str,
+    ) -> Result<(), ResponseError> {
+        self.execute(
+            secret,
+            &self.revocation_statements,
+            username,
+            "",
+            Utc::now(),
+        )
+        .await
+    }
+
+    /// Runs a set of statements against the target database, after replacing
+    /// the placeholders. The values are all generated by vssv and can't
+    /// contain quotes, so they can be inserted as-is.
+    async fn execute(
+        &self,
+        secret: &Secret,
+        statements: &str,
+        username: &str,
+        password: &str,
+        expires_at: DateTime<Utc>,
+    ) -> Result<(), ResponseError> {
+        let url = String::from_utf8(secret.contents.clone().unwrap_or_default())
+            .context("database connection URL is not valid UTF-8")?;
+        let statements = statements
+            .replace("{{name}}", username)
+            .replace("{{password}}", password)
+            .replace(
+                "{{expiration}}",
+                &expires_at.format("%Y-%m-%d %H:%M:%S%:z").to_string(),
+            );
+
+        let mut conn = PgConnection::connect(url.trim())
+            .await
+            .with_context(|| format!("could not connect to database `{}`", self.name))?;
+        conn.execute(sqlx::raw_sql(&statements))
+            .await
+            .with_context(|| format!("statements failed on database `{}`", self.name))?;
+        conn.close().await?;
+
+        Ok(())
+    }
+}
+
+impl DatabaseLease {
+    /// Stores the role that was created for a lease.
+    pub async fn create<'e>(
+        db: impl PgExecutor<'e>,
+        lease: Uuid,
+        connection: Uuid,
+        username: &str,
+    ) -> Result<Self, sqlx::Error> {
+        sqlx::query_as!(
+            Self,
+            "insert into database_leases (uuid, connection, username) values ($1, $2, $3) returning connection, username",
+            lease,
+            connection,
+            username
+        )
+        .fetch_one(db)
+        .await
+    }
+
+    /// Finds the role that was created for a lease.
+    pub async fn find<'e>(db: impl PgExecutor<'e>, uuid: Uuid) -> Result<Self, sqlx::Error> {
+        sqlx::query_as!(
+            Self,
+            "select connection, username from database_leases where uuid = $1",
+            uuid
+        )
+        .fetch_one(db)
+        .await
+    }
+}
+
+fn random_string(length: usize) -> String {
+    OsRng
+        .sample_iter(&Alphanumeric)
+        .take(length)
+        .map(char::from)
+        .collect()
+}
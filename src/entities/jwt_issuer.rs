@@ -0,0 +1,333 @@
+use std::time::Duration;
+
+use anyhow::{Context, bail};
+use jsonwebtoken::{AlgorithmFamily, DecodingKey, Validation, jwk::JwkSet};
+use serde_json::{Map, Value};
+use sqlx::PgExecutor;
+use uuid::Uuid;
+
+use crate::errors::ResponseError;
+
+const JWKS_FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The verified claims of a JWT.
+pub type JwtClaims = Map<String, Value>;
+
+/// A trusted JWT issuer, like a CI provider's OIDC issuer. Its signing keys are
+/// either stored as a JWKS document, or fetched from a URL.
+#[derive(Debug)]
+pub struct JwtIssuer {
+    pub uuid: Uuid,
+    pub name: String,
+    pub issuer: String,
+    pub jwks: Option<Value>,
+    pub jwks_url: Option<String>,
+    pub token_ttl_seconds: i64,
+}
+
+/// Grants a role to tokens created from JWTs with matching claims.
+#[derive(Debug)]
+pub struct JwtBinding {
+    pub role: Uuid,
+    pub audience: String,
+    pub subject_pattern: String,
+    pub claims: Value,
+}
+
+impl JwtIssuer {
+    /// Tries to find an issuer by the value of its JWTs' `iss` claim. If
+    /// nothing is found, it will result with None().
+    pub async fn try_find_by_issuer<'e>(
+        db: impl PgExecutor<'e>,
+        issuer: &str,
+    ) -> Result<Option<Self>, sqlx::Error> {
+        sqlx::query_as!(
+            Self,
+            r#"select
+              uuid, name, issuer, jwks, jwks_url,
+              extract(epoch from token_ttl)::bigint as "token_ttl_seconds!"
+            from jwt_issuers where issuer = $1"#,
+            issuer
+        )
+        .fetch_optional(db)
+        .await
+    }
+
+    /// Returns all bindings of this issuer.
+    pub async fn bindings<'e>(
+        &self,
+        db: impl PgExecutor<'e>,
+    ) -> Result<Vec<JwtBinding>, sqlx::Error> {
+        sqlx::query_as!(
+            JwtBinding,
+            "select role, audience, subject_pattern, claims from jwt_bindings where issuer = $1",
+            self.uuid
+        )
+        .fetch_all(db)
+        .await
+    }
+
+    /// Loads the issuer's signing keys, either from the stored JWKS document,
+    /// or by fetching them from the JWKS URL.
+    pub async fn key_set(&self) -> Result<JwkSet, ResponseError> {
+        if let Some(jwks) = &self.jwks {
+            return Ok(serde_json::from_value(jwks.clone())
+                .with_context(|| format!("invalid JWKS for issuer `{}`", self.name))?);
+        }
+
+        let url = self.jwks_url.as_deref().unwrap_or_default();
+        let key_set = reqwest::Client::builder()
+            .timeout(JWKS_FETCH_TIMEOUT)
+            .build()
+            .context("could not create HTTP client")?
+            .get(url)
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status)
+            .with_context(|| format!("could not fetch JWKS from `{}`", url))?
+            .json()
+            .await
+            .with_context(|| format!("invalid JWKS at `{}`", url))?;
+
+        Ok(key_set)
+    }
+
+    /// Verifies a JWT's signature, its expiration, and that it was issued by
+    /// this issuer for one of the given audiences. Only asymmetric algorithms
+    /// are accepted. Returns the JWT's claims, or an error describing why the
+    /// JWT was rejected.
+    pub fn verify(
+        &self,
+        jwt: &str,
+        key_set: &JwkSet,
+        audiences: &[&str],
+    ) -> anyhow::Result<JwtClaims> {
+        let header = jsonwebtoken::decode_header(jwt)?;
+        if header.alg.family() == AlgorithmFamily::Hmac {
+            bail!("symmetric algorithm `{:?}` is not allowed", header.alg);
+        }
+
+        let jwk = match &header.kid {
+            Some(kid) => key_set.find(kid),
+            None if key_set.keys.len() == 1 => key_set.keys.first(),
+            None => None,
+        }
+        .context("no matching key in the issuer's key set")?;
+
+        let mut validation = Validation::new(header.alg);
+        validation.set_issuer(&[&self.issuer]);
+        validation.set_audience(audiences);
+        validation.set_required_spec_claims(&["exp", "iss", "aud", "sub"]);
+
+        Ok(jsonwebtoken::decode(jwt, &DecodingKey::from_jwk(jwk)?, &validation)?.claims)
+    }
+}
+
+impl JwtBinding {
+    /// Checks if a JWT's claims match this binding.
+    pub fn matches(&self, claims: &JwtClaims) -> bool {
+        let audience_matches = match claims.get("aud") {
+            Some(Value::String(audience)) => *audience == self.audience,
+            Some(Value::Array(audiences)) => audiences
+                .iter()
+                .any(|audience| audience.as_str() == Some(&self.audience)),
+            _ => false,
+        };
+        let subject_matches = claims
+            .get("sub")
+            .and_then(Value::as_str)
+            .is_some_and(|subject| glob_matches(&self.subject_pattern, subject));
+        let claims_match = self.claims.as_object().is_some_and(|expected| {
+            expected
+                .iter()
+                .all(|(name, value)| claims.get(name) == Some(value))
+        });
+
+        audience_matches && subject_matches && claims_match
+    }
+}
+
+/// Reads the `iss` claim of a JWT without verifying anything, so the right
+/// issuer can be looked up.
+pub fn unverified_issuer(jwt: &str) -> Option<String> {
+    jsonwebtoken::dangerous::insecure_decode::<JwtClaims>(jwt)
+        .ok()?
+        .claims
+        .get("iss")?
+        .as_str()
+        .map(str::to_string)
+}
+
+/// Checks if a value matches a pattern, where `*` matches any sequence of
+/// characters, including none.
+fn glob_matches(pattern: &str, value: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let value: Vec<char> = value.chars().collect();
+
+    let (mut p, mut v) = (0, 0);
+    let mut backtrack = None;
+    while v < value.len() {
+        if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p, v));
+            p += 1;
+        } else if p < pattern.len() && pattern[p] == value[v] {
+            p += 1;
+            v += 1;
+        } else if let Some((star, matched)) = backtrack {
+            backtrack = Some((star, matched + 1));
+            p = star + 1;
+            v = matched + 1;
+        } else {
+            return false;
+        }
+    }
+
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+    use ed25519_dalek::{SigningKey, pkcs8::EncodePrivateKey};
+    use jsonwebtoken::{Algorithm, EncodingKey, Header};
+    use serde_json::json;
+
+    use super::*;
+
+    fn issuer() -> JwtIssuer {
+        JwtIssuer {
+            uuid: Uuid::nil(),
+            name: "ci".to_string(),
+            issuer: "https://ci.example.com".to_string(),
+            jwks: None,
+            jwks_url: None,
+            token_ttl_seconds: 900,
+        }
+    }
+
+    fn signing_key(seed: u8) -> SigningKey {
+        SigningKey::from_bytes(&[seed; 32])
+    }
+
+    fn key_set(seed: u8, kid: &str) -> JwkSet {
+        let x = URL_SAFE_NO_PAD.encode(signing_key(seed).verifying_key().as_bytes());
+        serde_json::from_value(json!({
+            "keys": [{ "kty": "OKP", "crv": "Ed25519", "alg": "EdDSA", "kid": kid, "x": x }]
+        }))
+        .unwrap()
+    }
+
+    fn jwt(seed: u8, kid: Option<&str>, claims: Value) -> String {
+        let der = signing_key(seed).to_pkcs8_der().unwrap();
+        let mut header = Header::new(Algorithm::EdDSA);
+        header.kid = kid.map(str::to_string);
+        jsonwebtoken::encode(&header, &claims, &EncodingKey::from_ed_der(der.as_bytes())).unwrap()
+    }
+
+    fn claims(overrides: Value) -> Value {
+        let mut claims = json!({
+            "iss": "https://ci.example.com",
+            "aud": "vssv",
+            "sub": "repo:acme/app:ref:refs/heads/main",
+            "exp": chrono::Utc::now().timestamp() + 300,
+        });
+        claims
+            .as_object_mut()
+            .unwrap()
+            .extend(overrides.as_object().unwrap().clone());
+        claims
+    }
+
+    fn binding(subject_pattern: &str, claims: Value) -> JwtBinding {
+        JwtBinding {
+            role: Uuid::nil(),
+            audience: "vssv".to_string(),
+            subject_pattern: subject_pattern.to_string(),
+            claims,
+        }
+    }
+
+    #[test]
+    fn matches_globs() {
+        assert!(glob_matches("repo:acme/*", "repo:acme/app:ref:main"));
+        assert!(glob_matches("repo:*/app:*", "repo:acme/app:ref:main"));
+        assert!(glob_matches("*", ""));
+        assert!(glob_matches("a*b*c", "abbbc"));
+        assert!(glob_matches("exact", "exact"));
+        assert!(!glob_matches("exact", "exactly"));
+        assert!(!glob_matches("repo:acme/*", "repo:other/app"));
+        assert!(!glob_matches("a*c", "abcd"));
+    }
+
+    #[test]
+    fn reads_unverified_issuers() {
+        assert_eq!(
+            unverified_issuer(&jwt(1, None, claims(json!({})))),
+            Some("https://ci.example.com".to_string())
+        );
+        assert_eq!(unverified_issuer("not a jwt"), None);
+    }
+
+    #[test]
+    fn verifies_jwts() {
+        let verified = issuer()
+            .verify(
+                &jwt(1, Some("k1"), claims(json!({}))),
+                &key_set(1, "k1"),
+                &["vssv"],
+            )
+            .unwrap();
+        assert_eq!(verified["sub"], "repo:acme/app:ref:refs/heads/main");
+
+        let without_kid = jwt(1, None, claims(json!({})));
+        assert!(
+            issuer()
+                .verify(&without_kid, &key_set(1, "k1"), &["vssv"])
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_jwts() {
+        let key_set = key_set(1, "k1");
+        for jwt in [
+            jwt(2, Some("k1"), claims(json!({}))),
+            jwt(1, Some("k2"), claims(json!({}))),
+            jwt(
+                1,
+                Some("k1"),
+                claims(json!({ "iss": "https://evil.example.com" })),
+            ),
+            jwt(1, Some("k1"), claims(json!({ "aud": "someone-else" }))),
+            jwt(1, Some("k1"), claims(json!({ "exp": 1 }))),
+        ] {
+            assert!(issuer().verify(&jwt, &key_set, &["vssv"]).is_err());
+        }
+
+        let hmac = jsonwebtoken::encode(
+            &Header::new(Algorithm::HS256),
+            &claims(json!({})),
+            &EncodingKey::from_secret(b"secret"),
+        )
+        .unwrap();
+        assert!(issuer().verify(&hmac, &key_set, &["vssv"]).is_err());
+    }
+
+    #[test]
+    fn matches_bindings() {
+        let claims =
+            |overrides| -> JwtClaims { serde_json::from_value(self::claims(overrides)).unwrap() };
+
+        let binding = binding("repo:acme/*", json!({ "environment": "prod" }));
+        assert!(binding.matches(&claims(json!({ "environment": "prod" }))));
+        assert!(binding.matches(&claims(
+            json!({ "environment": "prod", "aud": ["x", "vssv"] })
+        )));
+        assert!(!binding.matches(&claims(json!({}))));
+        assert!(!binding.matches(&claims(json!({ "environment": "staging" }))));
+        assert!(!binding.matches(&claims(json!({ "environment": "prod", "aud": "x" }))));
+        assert!(!binding.matches(&claims(
+            json!({ "environment": "prod", "sub": "repo:other/app" })
+        )));
+    }
+}
@@ -0,0 +1,236 @@
+use chrono::{DateTime, Duration, Utc};
+use sqlx::{PgConnection, PgExecutor};
+use uuid::Uuid;
+
+use crate::{
+    entities::{AuditLogAction, DatabaseConnection, DatabaseLease, Secret},
+    errors::ResponseError,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, sqlx::Type)]
+#[sqlx(type_name = "lease_kind", rename_all = "snake_case")]
+pub enum LeaseKind {
+    DatabaseCredentials,
+    Certificate,
+}
+
+impl LeaseKind {
+    /// The action that gets logged in the audit log when a lease of this kind
+    /// gets revoked.
+    pub fn revoke_action(&self) -> AuditLogAction {
+        match self {
+            Self::DatabaseCredentials => AuditLogAction::DatabaseCredentialsRevoke,
+            Self::Certificate => AuditLogAction::CertificateRevoke,
+        }
+    }
+}
+
+/// Something vssv handed out for a limited time. Once a lease expires or gets
+/// revoked, whatever it was for gets cleaned up.
+#[derive(Debug)]
+pub struct Lease {
+    pub uuid: Uuid,
+    pub kind: LeaseKind,
+    pub path: String,
+    pub secret: Uuid,
+    pub token: Option<Uuid>,
+    pub expires_at: DateTime<Utc>,
+    pub max_expires_at: DateTime<Utc>,
+}
+
+impl Lease {
+    /// Stores a new lease that expires after `ttl_seconds`, and can be renewed
+    /// up to `max_ttl_seconds` after its creation.
+    pub async fn create<'e>(
+        db: impl PgExecutor<'e>,
+        kind: LeaseKind,
+        path: &str,
+        secret: Uuid,
+        token: Uuid,
+        ttl_seconds: i64,
+        max_ttl_seconds: i64,
+    ) -> Result<Self, sqlx::Error> {
+        sqlx::query_as!(
+            Self,
+            r#"insert into leases (kind, path, secret, token, expires_at, max_expires_at)
+            values ($1, $2, $3, $4, now() + make_interval(secs => $5), now() + make_interval(secs => $6))
+            returning
+              uuid, kind as "kind: LeaseKind", path, secret, token,
+              expires_at, max_expires_at"#,
+            kind as LeaseKind,
+            path,
+            secret,
+            token,
+            ttl_seconds as f64,
+            max_ttl_seconds as f64
+        )
+        .fetch_one(db)
+        .await
+    }
+
+    /// Tries to find a lease that hasn't been revoked yet, and locks it until
+    /// the end of the transaction. Revoked leases result in None().
+    pub async fn try_find_active_for_update(
+        conn: &mut PgConnection,
+        uuid: Uuid,
+    ) -> Result<Option<Self>, sqlx::Error> {
+        sqlx::query_as!(
+            Self,
+            r#"select
+              uuid, kind as "kind: LeaseKind", path, secret, token,
+              expires_at, max_expires_at
+            from leases where uuid = $1 and revoked_at is null for update"#,
+            uuid
+        )
+        .fetch_optional(conn)
+        .await
+    }
+
+    /// Finds all leases that haven't been revoked yet and whose path starts
+    /// with `prefix`, and locks them until the end of the transaction.
+    pub async fn find_active_by_prefix_for_update(
+        conn: &mut PgConnection,
+        prefix: &str,
+    ) -> Result<Vec<Self>, sqlx::Error> {
+        sqlx::query_as!(
+            Self,
+            r#"select
+              uuid, kind as "kind: LeaseKind", path, secret, token,
+              expires_at, max_expires_at
+            from leases where revoked_at is null and starts_with(path, $1)
+            order by path for update"#,
+            prefix
+        )
+        .fetch_all(conn)
+        .await
+    }
+
+    /// Finds all expired leases that haven't been revoked yet, and locks them
+    /// until the end of the transaction. Leases that are locked by someone else,
+    /// for example because they're getting revoked right now, are skipped.
+    pub async fn find_expired_for_update(
+        conn: &mut PgConnection,
+    ) -> Result<Vec<Self>, sqlx::Error> {
+        sqlx::query_as!(
+            Self,
+            r#"select
+              uuid, kind as "kind: LeaseKind", path, secret, token,
+              expires_at, max_expires_at
+            from leases where revoked_at is null and expires_at <= now()
+            order by expires_at for update skip locked"#,
+        )
+        .fetch_all(conn)
+        .await
+    }
+
+    /// Checks if a token is allowed to manage this lease, which is only the
+    /// case for the token that created it, and for superuser tokens.
+    pub fn is_owned_by(&self, token_uuid: Uuid, superuser: bool) -> bool {
+        superuser || self.token == Some(token_uuid)
+    }
+
+    /// Extends the lease by `ttl_seconds` from now. Without a TTL, the lease is
+    /// extended up to its maximum, which the new expiration can't exceed. The
+    /// lease should be locked by the caller's transaction.
+    pub async fn renew(
+        &mut self,
+        conn: &mut PgConnection,
+        ttl_seconds: Option<i64>,
+    ) -> Result<(), ResponseError> {
+        let now = Utc::now();
+        if self.expires_at <= now {
+            return Err(ResponseError::NotFoundError());
+        }
+
+        let max_ttl = (self.max_expires_at - now).num_seconds();
+        let ttl = ttl_seconds.unwrap_or(max_ttl);
+        if !(1..=max_ttl).contains(&ttl) {
+            return Err(ResponseError::InvalidTtl(max_ttl.max(0)));
+        }
+        let expires_at = now + Duration::seconds(ttl);
+
+        match self.kind {
+            LeaseKind::DatabaseCredentials => {
+                let (database_lease, connection, secret) =
+                    load_database_lease(conn, self.uuid).await?;
+                connection
+                    .renew_credentials(&secret, &database_lease.username, expires_at)
+                    .await?;
+            }
+            LeaseKind::Certificate => return Err(ResponseError::LeaseNotRenewable()),
+        }
+
+        sqlx::query!(
+            "update leases set expires_at = $2 where uuid = $1",
+            self.uuid,
+            expires_at
+        )
+        .execute(&mut *conn)
+        .await?;
+        self.expires_at = expires_at;
+
+        Ok(())
+    }
+
+    /// Revokes the lease before it expires. Database roles get dropped, and
+    /// certificates are added to the CA's revocation list. The lease should be
+    /// locked by the caller's transaction.
+    pub async fn revoke(&self, conn: &mut PgConnection) -> Result<(), ResponseError> {
+        match self.kind {
+            LeaseKind::DatabaseCredentials => self.drop_database_credentials(conn).await?,
+            LeaseKind::Certificate => {
+                sqlx::query!(
+                    "update issued_certificates set revoked_at = now() where lease = $1 and revoked_at is null",
+                    self.uuid
+                )
+                .execute(&mut *conn)
+                .await?;
+            }
+        }
+
+        self.mark_revoked(conn).await
+    }
+
+    /// Cleans up after an expired lease. Unlike [Self::revoke], this doesn't
+    /// add certificates to the revocation list, as they have expired anyway.
+    pub async fn expire(&self, conn: &mut PgConnection) -> Result<(), ResponseError> {
+        match self.kind {
+            LeaseKind::DatabaseCredentials => self.drop_database_credentials(conn).await?,
+            LeaseKind::Certificate => {}
+        }
+
+        self.mark_revoked(conn).await
+    }
+
+    async fn drop_database_credentials(
+        &self,
+        conn: &mut PgConnection,
+    ) -> Result<(), ResponseError> {
+        let (database_lease, connection, secret) = load_database_lease(conn, self.uuid).await?;
+        connection
+            .revoke_credentials(&secret, &database_lease.username)
+            .await
+    }
+
+    async fn mark_revoked(&self, conn: &mut PgConnection) -> Result<(), ResponseError> {
+        sqlx::query!(
+            "update leases set revoked_at = now() where uuid = $1",
+            self.uuid
+        )
+        .execute(conn)
+        .await?;
+
+        Ok(())
+    }
+}
+
+async fn load_database_lease(
+    conn: &mut PgConnection,
+    uuid: Uuid,
+) -> Result<(DatabaseLease, DatabaseConnection, Secret), sqlx::Error> {
+    let database_lease = DatabaseLease::find(&mut *conn, uuid).await?;
+    let connection = DatabaseConnection::find(&mut *conn, database_lease.connection).await?;
+    let secret = Secret::find(&mut *conn, connection.secret).await?;
+
+    Ok((database_lease, connection, secret))
+}
@@ -1,17 +1,38 @@
+use std::collections::BTreeMap;
+
 use axum::{
+    Json,
     body::Body,
-    http::StatusCode,
+    http::{StatusCode, header},
     response::{IntoResponse, Response},
 };
-use sqlx::{PgExecutor, postgres::PgQueryResult};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::{PgExecutor, postgres::PgQueryResult, types::Json as SqlJson};
 use uuid::Uuid;
 
+use crate::{entities::SecretFormat, errors::ResponseError};
+
+/// The named fields of a structured Secret.
+pub type SecretFields = BTreeMap<String, String>;
+
+#[derive(Clone, Copy, Debug, PartialEq, sqlx::Type)]
+#[sqlx(type_name = "secret_kind", rename_all = "snake_case")]
+pub enum SecretKind {
+    Blob,
+    Structured,
+    Template,
+}
+
 /// A secret entry stored in the database.
 #[derive(Debug)]
 pub struct Secret {
     pub uuid: Uuid,
+    pub kind: SecretKind,
     pub file_name: Option<String>,
     pub contents: Option<Vec<u8>>,
+    pub fields: Option<SqlJson<SecretFields>>,
+    pub requires_approval: bool,
 }
 
 impl Secret {
@@ -20,13 +41,50 @@ impl Secret {
     pub async fn find<'e>(db: impl PgExecutor<'e>, uuid: Uuid) -> Result<Self, sqlx::Error> {
         sqlx::query_as!(
             Self,
-            "select uuid, file_name, contents from secrets where uuid = $1",
+            r#"select
+              uuid, kind as "kind: SecretKind", file_name, contents,
+              fields as "fields: SqlJson<SecretFields>", requires_approval
+            from secrets where uuid = $1"#,
+            uuid
+        )
+        .fetch_one(db)
+        .await
+    }
+
+    /// Checks if a Secret with that UUID exists.
+    pub async fn exists<'e>(db: impl PgExecutor<'e>, uuid: Uuid) -> Result<bool, sqlx::Error> {
+        sqlx::query_scalar!(
+            r#"select exists(select 1 from secrets where uuid = $1) as "exists!""#,
             uuid
         )
         .fetch_one(db)
         .await
     }
 
+    /// Resolves a Secret's path to its UUID. Returns None() if no Secret with
+    /// that path exists. Paths are just an alias, so all further lookups and
+    /// permission checks should still happen based on the UUID.
+    pub async fn try_find_uuid_by_path<'e>(
+        db: impl PgExecutor<'e>,
+        path: &str,
+    ) -> Result<Option<Uuid>, sqlx::Error> {
+        Ok(
+            sqlx::query!("select uuid from secrets where path = $1", path)
+                .fetch_optional(db)
+                .await?
+                .map(|row| row.uuid),
+        )
+    }
+
+    /// Returns the fields of a structured Secret. Structured Secrets without
+    /// any fields set, as well as blob Secrets, will return an empty map.
+    pub fn fields(&self) -> SecretFields {
+        self.fields
+            .as_ref()
+            .map(|fields| fields.0.clone())
+            .unwrap_or_default()
+    }
+
     /// Updates the contents of the Secret, both in the struct, but also in the
     /// database.
     pub async fn update_contents<'e>(
@@ -44,14 +102,113 @@ impl Secret {
         .execute(db)
         .await
     }
+
+    /// Updates individual fields of a structured Secret, both in the struct,
+    /// but also in the database. Fields with a value are set, fields without a
+    /// value are removed. All other fields are left untouched.
+    pub async fn patch_fields<'e>(
+        &mut self,
+        db: impl PgExecutor<'e>,
+        patch: BTreeMap<String, Option<String>>,
+    ) -> Result<(), sqlx::Error> {
+        let mut set = SecretFields::new();
+        let mut remove = vec![];
+        for (name, value) in patch {
+            match value {
+                Some(value) => {
+                    set.insert(name, value);
+                }
+                None => remove.push(name),
+            }
+        }
+
+        let fields = sqlx::query_scalar!(
+            r#"update secrets set fields = (coalesce(fields, '{}') || $1) - $2::text[]
+            where uuid = $3
+            returning fields as "fields!: SqlJson<SecretFields>""#,
+            SqlJson(set) as _,
+            &remove,
+            self.uuid
+        )
+        .fetch_one(db)
+        .await?;
+
+        self.fields = Some(fields);
+        Ok(())
+    }
+
+    /// Like [IntoResponse::into_response], but structured Secrets are rendered
+    /// in the given format instead of the default JSON. The format is ignored
+    /// for blob Secrets, as those are always returned as-is.
+    pub fn into_formatted_response(
+        self,
+        format: Option<SecretFormat>,
+    ) -> Result<Response, ResponseError> {
+        match format {
+            Some(format) if self.kind == SecretKind::Structured => {
+                let body = format.render(&self.fields())?;
+                Ok(([(header::CONTENT_TYPE, format.content_type())], body).into_response())
+            }
+            _ => Ok(self.into_response()),
+        }
+    }
+}
+
+/// A Secret's metadata, as shown to tokens that want to know which secrets they
+/// can access. This intentionally never contains the secret's contents.
+#[derive(Debug, Serialize)]
+pub struct SecretSummary {
+    pub uuid: Uuid,
+    pub path: Option<String>,
+    pub file_name: Option<String>,
+    pub tags: Vec<String>,
+    pub updated_at: DateTime<Utc>,
+    pub can_read: bool,
+    pub can_write: bool,
+}
+
+impl SecretSummary {
+    /// Lists all secrets a token can either read or write. If `tags` is not
+    /// empty, only secrets that have all of those tags are returned. Superuser
+    /// tokens get to see everything.
+    pub async fn list_accessible<'e>(
+        db: impl PgExecutor<'e>,
+        token_uuid: Uuid,
+        superuser: bool,
+        tags: &[String],
+    ) -> Result<Vec<Self>, sqlx::Error> {
+        sqlx::query_as!(
+            Self,
+            r#"select
+              s.uuid, s.path, s.file_name, s.tags, s.updated_at,
+              ($2 or coalesce(a.can_read, false)) as "can_read!",
+              ($2 or coalesce(a.can_write, false)) as "can_write!"
+            from secrets s
+            left join token_secret_access a on a.secret = s.uuid and a.token = $1
+            where s.tags @> $3 and ($2 or a.can_read or a.can_write)
+            order by s.path, s.uuid"#,
+            token_uuid,
+            superuser,
+            tags
+        )
+        .fetch_all(db)
+        .await
+    }
 }
 
 impl IntoResponse for Secret {
-    /// Simpl [IntoResponse] implementation for the Secret. Will return an empty
-    /// response with a 204 status code if there is no content. If there is
-    /// content, it will respond with it. The `content-disposition` header will
-    /// contain the target filename if the field is set in the database.
+    /// Simpl [IntoResponse] implementation for the Secret. For blob secrets,
+    /// it will return an empty response with a 204 status code if there is no
+    /// content. If there is content, it will respond with it. The
+    /// `content-disposition` header will contain the target filename if the
+    /// field is set in the database. Structured secrets are returned as a JSON
+    /// object containing all fields. Template secrets are treated like blob
+    /// secrets, so they have to be rendered into the contents beforehand.
     fn into_response(self) -> axum::response::Response {
+        if self.kind == SecretKind::Structured {
+            return Json(self.fields()).into_response();
+        }
+
         let dispo_header = match &self.file_name {
             None => "attachment".to_string(),
             Some(file_name) => format!(r#"attachment; filename="{}""#, file_name),
@@ -0,0 +1,201 @@
+use axum::{
+    http::header,
+    response::{IntoResponse, Response},
+};
+use chrono::Utc;
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::{
+    entities::{Secret, SecretFormat, SecretKind},
+    errors::ResponseError,
+};
+
+/// An entry in a bundle's manifest. Each requested secret gets an entry, even
+/// if it was skipped because the token isn't allowed to read it.
+#[derive(Debug, Serialize)]
+struct ManifestEntry {
+    requested: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    uuid: Option<Uuid>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    file: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<&'static str>,
+}
+
+/// A tar archive containing multiple secrets. Each secret is stored as
+/// `UUID/FILE_NAME`, and a `manifest.json` in the archive's root maps the
+/// requested identifiers to those files.
+pub struct SecretBundle {
+    format: SecretFormat,
+    archive: tar::Builder<Vec<u8>>,
+    manifest: Vec<ManifestEntry>,
+}
+
+impl SecretBundle {
+    /// Creates an empty bundle. Structured secrets will be rendered in the
+    /// given format.
+    pub fn new(format: Option<SecretFormat>) -> Self {
+        Self {
+            format: format.unwrap_or(SecretFormat::Json),
+            archive: tar::Builder::new(vec![]),
+            manifest: vec![],
+        }
+    }
+
+    /// Checks if a secret is already part of this bundle, because it was
+    /// requested before, maybe via a different identifier. If it is, the
+    /// manifest gets an entry for `requested` pointing to the same file, and
+    /// this returns `true`.
+    pub fn try_add_alias(&mut self, requested: &str, uuid: Uuid) -> bool {
+        let Some(file) = self
+            .manifest
+            .iter()
+            .find(|entry| entry.uuid == Some(uuid))
+            .and_then(|entry| entry.file.clone())
+        else {
+            return false;
+        };
+
+        self.manifest.push(ManifestEntry {
+            requested: requested.to_string(),
+            uuid: Some(uuid),
+            file: Some(file),
+            error: None,
+        });
+        true
+    }
+
+    /// Adds a secret to the bundle. `requested` is the identifier the client
+    /// used to request the secret, which will be noted in the manifest. Template
+    /// secrets have to be rendered beforehand.
+    pub fn add(&mut self, requested: String, secret: Secret) -> Result<(), ResponseError> {
+        let (default_file_name, contents) = match secret.kind {
+            SecretKind::Blob | SecretKind::Template => {
+                ("contents".to_string(), secret.contents.unwrap_or_default())
+            }
+            SecretKind::Structured => (
+                format!("fields.{}", self.format.file_extension()),
+                self.format.render(&secret.fields())?.into_bytes(),
+            ),
+        };
+        let file_name = secret
+            .file_name
+            .as_deref()
+            .map(sanitize_file_name)
+            .unwrap_or(default_file_name);
+        let path = format!("{}/{}", secret.uuid, file_name);
+
+        self.append_file(&path, &contents)?;
+        self.manifest.push(ManifestEntry {
+            requested,
+            uuid: Some(secret.uuid),
+            file: Some(path),
+            error: None,
+        });
+
+        Ok(())
+    }
+
+    /// Notes a secret that was requested, but not included because the token
+    /// isn't allowed to read it.
+    pub fn skip_unauthorized(&mut self, requested: String) {
+        self.manifest.push(ManifestEntry {
+            requested,
+            uuid: None,
+            file: None,
+            error: Some("unauthorized"),
+        });
+    }
+
+    /// Finishes the archive by adding the manifest, and turns it into a
+    /// response.
+    pub fn finish(mut self) -> Result<Response, ResponseError> {
+        let manifest =
+            serde_json::to_vec_pretty(&self.manifest).expect("manifest to be serializable");
+        self.append_file("manifest.json", &manifest)?;
+
+        Ok((
+            [
+                (header::CONTENT_TYPE, "application/x-tar"),
+                (
+                    header::CONTENT_DISPOSITION,
+                    r#"attachment; filename="secrets.tar""#,
+                ),
+            ],
+            self.archive.into_inner()?,
+        )
+            .into_response())
+    }
+
+    fn append_file(&mut self, path: &str, contents: &[u8]) -> Result<(), std::io::Error> {
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(tar::EntryType::Regular);
+        header.set_size(contents.len() as u64);
+        header.set_mode(0o600);
+        header.set_mtime(Utc::now().timestamp().try_into().unwrap_or_default());
+        self.archive.append_data(&mut header, path, contents)
+    }
+}
+
+/// File names are free-form in the database, so this makes sure they can't
+/// escape the secret's directory inside the archive.
+fn sanitize_file_name(file_name: &str) -> String {
+    match file_name.replace(['/', '\\'], "_").as_str() {
+        "" | "." | ".." => "contents".to_string(),
+        sanitized => sanitized.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sanitizes_file_names() {
+        assert_eq!(sanitize_file_name("id_ed25519"), "id_ed25519");
+        assert_eq!(sanitize_file_name("../../etc/passwd"), ".._.._etc_passwd");
+        assert_eq!(sanitize_file_name(r"..\evil"), ".._evil");
+        for name in ["", ".", ".."] {
+            assert_eq!(sanitize_file_name(name), "contents");
+        }
+    }
+
+    #[test]
+    fn aliases_point_to_the_same_file() {
+        let uuid = Uuid::new_v4();
+        let mut bundle = SecretBundle::new(None);
+        assert!(!bundle.try_add_alias("prod/db", uuid));
+
+        bundle
+            .add(
+                "prod/db".to_string(),
+                Secret {
+                    uuid,
+                    kind: SecretKind::Blob,
+                    file_name: Some("pw.txt".to_string()),
+                    contents: Some(b"hunter2".to_vec()),
+                    fields: None,
+                    requires_approval: false,
+                },
+            )
+            .unwrap();
+        assert!(bundle.try_add_alias(&uuid.to_string(), uuid));
+        assert!(!bundle.try_add_alias("prod/other", Uuid::new_v4()));
+
+        let files: Vec<_> = bundle
+            .manifest
+            .iter()
+            .map(|entry| (entry.requested.as_str(), entry.file.as_deref()))
+            .collect();
+        let file = format!("{}/pw.txt", uuid);
+        assert_eq!(
+            files,
+            [
+                ("prod/db", Some(file.as_str())),
+                (uuid.to_string().as_str(), Some(file.as_str()))
+            ]
+        );
+    }
+}
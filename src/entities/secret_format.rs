@@ -0,0 +1,320 @@
+use axum::{
+    extract::{FromRequestParts, Query},
+    http::{header, request::Parts},
+};
+use serde::Deserialize;
+
+use crate::{entities::secret::SecretFields, errors::ResponseError};
+
+/// The formats a structured Secret can be rendered as.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum SecretFormat {
+    Json,
+    Dotenv,
+    Yaml,
+    Toml,
+    Shell,
+}
+
+impl SecretFormat {
+    /// Maps a media type from an Accept header to a format. Returns None() for
+    /// everything we don't know, including wildcards.
+    fn from_media_type(media_type: &str) -> Option<Self> {
+        match media_type {
+            "application/json" => Some(Self::Json),
+            "application/x-dotenv" | "text/x-dotenv" => Some(Self::Dotenv),
+            "application/yaml" | "application/x-yaml" | "text/yaml" => Some(Self::Yaml),
+            "application/toml" => Some(Self::Toml),
+            "application/x-sh" | "text/x-shellscript" => Some(Self::Shell),
+            _ => None,
+        }
+    }
+
+    /// Picks the first format we know from an Accept header. Quality values are
+    /// ignored, clients that care should use the `format` query instead.
+    fn from_accept_header(accept: &str) -> Option<Self> {
+        accept
+            .split(',')
+            .filter_map(|entry| entry.split(';').next())
+            .find_map(|media_type| Self::from_media_type(&media_type.trim().to_lowercase()))
+    }
+
+    /// The content-type header value for responses in this format.
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            Self::Json => "application/json",
+            Self::Dotenv => "text/plain; charset=utf-8",
+            Self::Yaml => "application/yaml",
+            Self::Toml => "application/toml",
+            Self::Shell => "text/x-shellscript; charset=utf-8",
+        }
+    }
+
+    /// The file extension used when a file in this format needs a name.
+    pub fn file_extension(&self) -> &'static str {
+        match self {
+            Self::Json => "json",
+            Self::Dotenv => "env",
+            Self::Yaml => "yaml",
+            Self::Toml => "toml",
+            Self::Shell => "sh",
+        }
+    }
+
+    /// Renders a set of fields in this format. This fails if a field name can't
+    /// be represented, for example because it's not a valid variable name in
+    /// .env files and shell exports, or if a value can't be quoted safely.
+    pub fn render(&self, fields: &SecretFields) -> Result<String, ResponseError> {
+        match self {
+            Self::Json => {
+                Ok(serde_json::to_string_pretty(fields).expect("string maps to be serializable"))
+            }
+            Self::Dotenv => render_lines(fields, |name, value| {
+                Ok(format!(
+                    "{}={}",
+                    variable_name(name)?,
+                    quote_dotenv(name, value)?
+                ))
+            }),
+            Self::Yaml if fields.is_empty() => Ok("{}\n".to_string()),
+            Self::Yaml => render_lines(fields, |name, value| {
+                Ok(format!("{}: {}", quote(name), quote(value)))
+            }),
+            Self::Toml => render_lines(fields, |name, value| {
+                Ok(format!("{} = {}", quote(name), quote(value)))
+            }),
+            Self::Shell => render_lines(fields, |name, value| {
+                Ok(format!(
+                    "export {}='{}'",
+                    variable_name(name)?,
+                    value.replace('\'', r"'\''")
+                ))
+            }),
+        }
+    }
+}
+
+/// Renders each field as its own line, using the given function.
+fn render_lines(
+    fields: &SecretFields,
+    render_line: impl Fn(&str, &str) -> Result<String, ResponseError>,
+) -> Result<String, ResponseError> {
+    fields
+        .iter()
+        .map(|(name, value)| render_line(name, value).map(|line| line + "\n"))
+        .collect()
+}
+
+/// Makes sure a field name can be used as an environment variable name, which
+/// is required for both .env files and shell exports.
+fn variable_name(name: &str) -> Result<&str, ResponseError> {
+    let mut chars = name.chars();
+    let valid = chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+
+    if valid {
+        Ok(name)
+    } else {
+        Err(ResponseError::UnrenderableFieldName(name.to_string()))
+    }
+}
+
+/// Quotes a value for a .env file, in the dialect of docker compose and
+/// godotenv, which python-dotenv also reads with `interpolate=False`.
+/// Single-quoted values are taken literally, but there is no way to escape a
+/// `'` in there, and python-dotenv treats backslashes as escapes. Everything
+/// else is double-quoted, where escapes work everywhere, but `$` would be
+/// interpolated, and there is no escape for it that all parsers understand.
+/// Values that need double quotes and contain a `$` are rejected.
+fn quote_dotenv(name: &str, value: &str) -> Result<String, ResponseError> {
+    if !value.contains(['\'', '\\']) {
+        return Ok(format!("'{}'", value));
+    }
+    if value.contains('$') {
+        return Err(ResponseError::UnrenderableFieldValue(name.to_string()));
+    }
+
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for c in value.chars() {
+        match c {
+            '\\' => quoted.push_str(r"\\"),
+            '"' => quoted.push_str(r#"\""#),
+            '\n' => quoted.push_str(r"\n"),
+            '\r' => quoted.push_str(r"\r"),
+            '\t' => quoted.push_str(r"\t"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    Ok(quoted)
+}
+
+/// Quotes a string as a JSON string. JSON strings are also valid double-quoted
+/// strings in both YAML and TOML, except that TOML also wants DEL escaped.
+fn quote(value: &str) -> String {
+    serde_json::to_string(value)
+        .expect("strings to be serializable")
+        .replace('\u{7f}', r"\u007f")
+}
+
+#[derive(Debug, Deserialize)]
+struct FormatQuery {
+    format: Option<SecretFormat>,
+}
+
+/// Extracts the requested [SecretFormat], if any. An explicit `format` query
+/// parameter always wins over the Accept header.
+#[derive(Debug)]
+pub struct ExtractSecretFormat(pub Option<SecretFormat>);
+
+impl<S> FromRequestParts<S> for ExtractSecretFormat
+where
+    S: Send + Sync,
+{
+    type Rejection = ResponseError;
+
+    /// Tries to read the `format` query parameter first, and rejects the request
+    /// with a 400 if it contains a format we don't know. If there is no such
+    /// parameter, the Accept header is used, and unknown types are ignored.
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let Query(query) = Query::<FormatQuery>::from_request_parts(parts, state)
+            .await
+            .map_err(|_| ResponseError::InvalidSecretFormat())?;
+        if let Some(format) = query.format {
+            return Ok(Self(Some(format)));
+        }
+
+        Ok(Self(
+            parts
+                .headers
+                .get(header::ACCEPT)
+                .and_then(|h| h.to_str().ok())
+                .and_then(SecretFormat::from_accept_header),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fields(entries: &[(&str, &str)]) -> SecretFields {
+        entries
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    fn render(format: SecretFormat, entries: &[(&str, &str)]) -> Result<String, ResponseError> {
+        format.render(&fields(entries))
+    }
+
+    #[test]
+    fn json() {
+        assert_eq!(
+            render(SecretFormat::Json, &[("b", "2"), ("a", "say \"hi\"\n")]).unwrap(),
+            "{\n  \"a\": \"say \\\"hi\\\"\\n\",\n  \"b\": \"2\"\n}"
+        );
+    }
+
+    #[test]
+    fn dotenv_single_quotes() {
+        assert_eq!(
+            render(
+                SecretFormat::Dotenv,
+                &[
+                    ("A", "plain"),
+                    ("B", "$HOME \"x\" ${Y}"),
+                    ("C", "two\nlines")
+                ]
+            )
+            .unwrap(),
+            "A='plain'\nB='$HOME \"x\" ${Y}'\nC='two\nlines'\n"
+        );
+    }
+
+    #[test]
+    fn dotenv_double_quotes() {
+        assert_eq!(
+            render(
+                SecretFormat::Dotenv,
+                &[("A", "it's"), ("B", r"C:\dir"), ("C", "'\"\t\r\n")]
+            )
+            .unwrap(),
+            "A=\"it's\"\nB=\"C:\\\\dir\"\nC=\"'\\\"\\t\\r\\n\"\n"
+        );
+    }
+
+    #[test]
+    fn dotenv_rejects_unquotable_values() {
+        for value in ["it's $5", r"\$HOME"] {
+            assert!(matches!(
+                render(SecretFormat::Dotenv, &[("A", value)]),
+                Err(ResponseError::UnrenderableFieldValue(name)) if name == "A"
+            ));
+        }
+    }
+
+    #[test]
+    fn yaml() {
+        assert_eq!(render(SecretFormat::Yaml, &[]).unwrap(), "{}\n");
+        assert_eq!(
+            render(
+                SecretFormat::Yaml,
+                &[("db-user", "no"), ("pw", "a: b # \"c\"")]
+            )
+            .unwrap(),
+            "\"db-user\": \"no\"\n\"pw\": \"a: b # \\\"c\\\"\"\n"
+        );
+    }
+
+    #[test]
+    fn toml() {
+        assert_eq!(
+            render(
+                SecretFormat::Toml,
+                &[("db.user", "x\u{7f}"), ("pw", "a\\b\n")]
+            )
+            .unwrap(),
+            "\"db.user\" = \"x\\u007f\"\n\"pw\" = \"a\\\\b\\n\"\n"
+        );
+    }
+
+    #[test]
+    fn shell() {
+        assert_eq!(
+            render(
+                SecretFormat::Shell,
+                &[("A", "it's $HOME"), ("B", "\\ \"\n")]
+            )
+            .unwrap(),
+            "export A='it'\\''s $HOME'\nexport B='\\ \"\n'\n"
+        );
+    }
+
+    #[test]
+    fn variable_names() {
+        for format in [SecretFormat::Dotenv, SecretFormat::Shell] {
+            assert!(render(format, &[("_A1", "x")]).is_ok());
+            for name in ["", "1A", "db-password", "a b", "ä"] {
+                assert!(matches!(
+                    render(format, &[(name, "x")]),
+                    Err(ResponseError::UnrenderableFieldName(_))
+                ));
+            }
+        }
+    }
+
+    #[test]
+    fn accept_header() {
+        assert_eq!(
+            SecretFormat::from_accept_header("text/html, Application/YAML;q=0.9, */*"),
+            Some(SecretFormat::Yaml)
+        );
+        assert_eq!(SecretFormat::from_accept_header("*/*"), None);
+    }
+}
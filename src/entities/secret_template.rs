@@ -0,0 +1,274 @@
+use std::collections::HashMap;
+
+use crate::errors::ResponseError;
+
+/// A reference to another secret inside a template, written as
+/// `{{ secret "REF" }}` or `{{ secret "REF" "FIELD" }}`. `REF` can be either a
+/// UUID or a path, `FIELD` is required for structured secrets.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct SecretReference {
+    pub secret: String,
+    pub field: Option<String>,
+}
+
+#[derive(Debug)]
+enum Segment<'a> {
+    Literal(&'a str),
+    Reference(SecretReference),
+}
+
+/// A parsed secret template. Everything that isn't a `{{ secret ... }}` tag is
+/// kept as-is, including other `{{ ... }}` tags, so templates for other tools
+/// can pass through.
+#[derive(Debug)]
+pub struct SecretTemplate<'a> {
+    segments: Vec<Segment<'a>>,
+}
+
+impl<'a> SecretTemplate<'a> {
+    /// Parses a template. This fails if a `{{ secret ... }}` tag is malformed
+    /// or never closed.
+    pub fn parse(source: &'a str) -> Result<Self, ResponseError> {
+        let mut segments = vec![];
+        let mut rest = source;
+
+        while let Some(start) = rest.find("{{") {
+            let Some(length) = tag_length(&rest[start..]) else {
+                if secret_args(&rest[start + 2..]).is_some() {
+                    return Err(ResponseError::TemplateError(
+                        "unterminated secret reference".to_string(),
+                    ));
+                }
+                break;
+            };
+            let tag = &rest[start..start + length];
+
+            match parse_tag(tag)? {
+                Some(reference) => {
+                    segments.push(Segment::Literal(&rest[..start]));
+                    segments.push(Segment::Reference(reference));
+                }
+                None => segments.push(Segment::Literal(&rest[..start + tag.len()])),
+            }
+            rest = &rest[start + tag.len()..];
+        }
+        segments.push(Segment::Literal(rest));
+
+        Ok(Self { segments })
+    }
+
+    /// Returns all secrets referenced in this template, without duplicates.
+    pub fn references(&self) -> Vec<&SecretReference> {
+        let mut references: Vec<&SecretReference> = vec![];
+        for segment in &self.segments {
+            if let Segment::Reference(reference) = segment
+                && !references.contains(&reference)
+            {
+                references.push(reference);
+            }
+        }
+        references
+    }
+
+    /// Renders the template, inserting the given value for each reference. The
+    /// values have to be resolved beforehand, see [Self::references].
+    pub fn render(&self, values: &HashMap<SecretReference, String>) -> String {
+        self.segments
+            .iter()
+            .map(|segment| match segment {
+                Segment::Literal(literal) => *literal,
+                Segment::Reference(reference) => values
+                    .get(reference)
+                    .expect("all references to be resolved before rendering"),
+            })
+            .collect()
+    }
+}
+
+/// Returns the length of the tag at the start of `source`, including the
+/// `{{` and `}}`. A `}}` inside a double-quoted string doesn't close the tag.
+/// Returns None() if the tag is never closed.
+fn tag_length(source: &str) -> Option<usize> {
+    let bytes = source.as_bytes();
+    let (mut quoted, mut escaped) = (false, false);
+
+    for i in 2..bytes.len() {
+        match bytes[i] {
+            _ if escaped => escaped = false,
+            b'\\' if quoted => escaped = true,
+            b'"' => quoted = !quoted,
+            b'}' if !quoted && bytes.get(i + 1) == Some(&b'}') => return Some(i + 2),
+            _ => {}
+        }
+    }
+
+    None
+}
+
+/// Returns the arguments of a tag's contents if it's a `secret` tag.
+fn secret_args(inner: &str) -> Option<&str> {
+    let args = inner.trim_start().strip_prefix("secret")?;
+    args.starts_with(char::is_whitespace).then_some(args)
+}
+
+/// Parses a single `{{ ... }}` tag. Returns None() if it's not a `secret` tag.
+fn parse_tag(tag: &str) -> Result<Option<SecretReference>, ResponseError> {
+    let Some(args) = secret_args(&tag[2..tag.len() - 2]) else {
+        return Ok(None);
+    };
+
+    let invalid = || ResponseError::TemplateError(format!("invalid secret reference `{}`", tag));
+    let mut args = parse_quoted_args(args).ok_or_else(invalid)?.into_iter();
+    match (args.next(), args.next(), args.next()) {
+        (Some(secret), field, None) => Ok(Some(SecretReference { secret, field })),
+        _ => Err(invalid()),
+    }
+}
+
+/// Parses a list of whitespace-separated, double-quoted strings. Inside the
+/// quotes, `\"` and `\\` can be used to escape quotes and backslashes.
+fn parse_quoted_args(args: &str) -> Option<Vec<String>> {
+    let mut parsed = vec![];
+    let mut chars = args.trim().chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => continue,
+            '"' => {
+                let mut arg = String::new();
+                loop {
+                    match chars.next()? {
+                        '"' => break,
+                        '\\' => arg.push(chars.next()?),
+                        c => arg.push(c),
+                    }
+                }
+                parsed.push(arg);
+            }
+            _ => return None,
+        }
+    }
+
+    Some(parsed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reference(secret: &str, field: Option<&str>) -> SecretReference {
+        SecretReference {
+            secret: secret.to_string(),
+            field: field.map(str::to_string),
+        }
+    }
+
+    fn references(source: &str) -> Vec<SecretReference> {
+        SecretTemplate::parse(source)
+            .unwrap()
+            .references()
+            .into_iter()
+            .cloned()
+            .collect()
+    }
+
+    #[test]
+    fn parses_references() {
+        assert_eq!(
+            references(r#"a={{ secret "prod/a" }} b={{secret "prod/b" "pw"}}"#),
+            vec![reference("prod/a", None), reference("prod/b", Some("pw"))]
+        );
+    }
+
+    #[test]
+    fn deduplicates_references() {
+        assert_eq!(
+            references(r#"{{ secret "a" }}{{ secret "a" }}{{ secret "a" "f" }}"#),
+            vec![reference("a", None), reference("a", Some("f"))]
+        );
+    }
+
+    #[test]
+    fn keeps_other_tags() {
+        let source = r#"{{ .Values.name }} {{ secrets "a" }} {{secret}} {{ unclosed"#;
+        let template = SecretTemplate::parse(source).unwrap();
+        assert!(template.references().is_empty());
+        assert_eq!(template.render(&HashMap::new()), source);
+    }
+
+    #[test]
+    fn closing_braces_inside_quotes() {
+        assert_eq!(
+            references(r#"{{ secret "a}}b" "c}}" }}"#),
+            vec![reference("a}}b", Some("c}}"))]
+        );
+    }
+
+    #[test]
+    fn nested_braces() {
+        assert_eq!(
+            references(r#"{{ secret "{{a}}" }}"#),
+            vec![reference("{{a}}", None)]
+        );
+
+        let template = SecretTemplate::parse(r#"{{{ secret "a" }}}"#).unwrap();
+        assert!(template.references().is_empty());
+    }
+
+    #[test]
+    fn escapes() {
+        assert_eq!(
+            references(r#"{{ secret "a\"}}\\b" }}"#),
+            vec![reference(r#"a"}}\b"#, None)]
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_references() {
+        for source in [
+            r#"{{ secret }}"#,
+            r#"{{ secret a }}"#,
+            r#"{{ secret "a" "b" "c" }}"#,
+            r#"{{ secret "a" b }}"#,
+        ] {
+            assert!(
+                matches!(
+                    SecretTemplate::parse(source),
+                    Err(ResponseError::TemplateError(_))
+                ),
+                "{}",
+                source
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_unterminated_references() {
+        for source in [
+            r#"{{ secret "a" "#,
+            r#"{{ secret "a }}"#,
+            r#"{{ secret "a\" }}"#,
+        ] {
+            assert!(
+                matches!(
+                    SecretTemplate::parse(source),
+                    Err(ResponseError::TemplateError(_))
+                ),
+                "{}",
+                source
+            );
+        }
+    }
+
+    #[test]
+    fn renders_values() {
+        let template =
+            SecretTemplate::parse(r#"user={{ secret "db" "user" }} pw={{ secret "pw" }}!"#)
+                .unwrap();
+        let values = HashMap::from([
+            (reference("db", Some("user")), "admin".to_string()),
+            (reference("pw", None), "hunter2".to_string()),
+        ]);
+        assert_eq!(template.render(&values), "user=admin pw=hunter2!");
+    }
+}
@@ -0,0 +1,383 @@
+use axum::{
+    body::Body,
+    extract::{FromRef, FromRequestParts, Request},
+    http::{HeaderMap, header, request::Parts},
+    middleware::Next,
+    response::Response,
+};
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use tracing::warn;
+use uuid::Uuid;
+
+use crate::{
+    AppState,
+    entities::{ExtractClientAddr, Token},
+    errors::ResponseError,
+};
+
+/// The scheme used in the Authorization header of signed requests.
+const SIGNATURE_SCHEME: &str = "VSSV-HMAC-SHA256";
+
+/// The largest body a signed request can have. This matches axum's default
+/// limit for extractors like [axum::Json].
+const MAX_SIGNED_BODY_SIZE: usize = 2 * 1024 * 1024;
+
+const MAX_NONCE_LENGTH: usize = 64;
+
+/// The SHA-256 digest of a signed request's body, hex-encoded. This gets added
+/// to the request's extensions by [digest_signed_body].
+#[derive(Clone, Debug)]
+struct BodyDigest(String);
+
+/// The contents of a signed request's Authorization header, which looks like
+/// `VSSV-HMAC-SHA256 token=UUID,timestamp=UNIX_TIME,nonce=NONCE,signature=HEX`.
+#[derive(Debug)]
+struct RequestSignature {
+    token: Uuid,
+    timestamp: i64,
+    nonce: String,
+    signature: Vec<u8>,
+}
+
+impl RequestSignature {
+    /// Checks if a request uses the signature scheme. This doesn't check if
+    /// the header is valid.
+    fn is_signed(headers: &HeaderMap) -> bool {
+        headers
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|value| value.starts_with(SIGNATURE_SCHEME))
+    }
+
+    /// Parses the Authorization header. Returns None() if anything is missing
+    /// or malformed.
+    fn parse(headers: &HeaderMap) -> Option<Self> {
+        let params = headers
+            .get(header::AUTHORIZATION)?
+            .to_str()
+            .ok()?
+            .strip_prefix(SIGNATURE_SCHEME)?
+            .strip_prefix(' ')?;
+
+        let (mut token, mut timestamp, mut nonce, mut signature) = (None, None, None, None);
+        for param in params.split(',') {
+            let (name, value) = param.trim().split_once('=')?;
+            match name {
+                "token" => token = Uuid::parse_str(value).ok(),
+                "timestamp" => timestamp = value.parse().ok(),
+                "nonce" => nonce = Some(value.to_string()),
+                "signature" => signature = hex::decode(value).ok(),
+                _ => return None,
+            }
+        }
+
+        let nonce = nonce.filter(|nonce| (1..=MAX_NONCE_LENGTH).contains(&nonce.len()))?;
+        Some(Self {
+            token: token?,
+            timestamp: timestamp?,
+            nonce,
+            signature: signature?,
+        })
+    }
+
+    /// Builds the string that gets signed. Every part is on its own line: the
+    /// method, the path including the query string, the timestamp, the nonce,
+    /// and the hex-encoded SHA-256 digest of the body.
+    fn string_to_sign(&self, parts: &Parts, body_digest: &str) -> String {
+        let path = parts
+            .uri
+            .path_and_query()
+            .map(|path| path.as_str())
+            .unwrap_or("/");
+        format!(
+            "{}\n{}\n{}\n{}\n{}",
+            parts.method, path, self.timestamp, self.nonce, body_digest
+        )
+    }
+
+    /// Checks the signature, using the token value as the HMAC key.
+    fn verify(&self, parts: &Parts, body_digest: &str, token_value: &str) -> bool {
+        let mut mac = Hmac::<Sha256>::new_from_slice(token_value.as_bytes())
+            .expect("HMAC can take keys of any size");
+        mac.update(self.string_to_sign(parts, body_digest).as_bytes());
+        mac.verify_slice(&self.signature).is_ok()
+    }
+
+    /// Remembers the nonce, and returns `false` if it has been used by the
+    /// same token before. The nonce is kept until the signed timestamp leaves
+    /// the replay window, so a request with a timestamp in the future can't be
+    /// replayed once the nonce is gone. A nonce that has expired already can
+    /// be used again.
+    async fn remember_nonce(&self, db: &PgPool, max_age_seconds: i64) -> Result<bool, sqlx::Error> {
+        let inserted = sqlx::query!(
+            "insert into request_nonces (token, nonce, expires_at)
+            values ($1, $2, to_timestamp($3) + make_interval(secs => $4))
+            on conflict (token, nonce) do update set created_at = now(), expires_at = excluded.expires_at
+            where request_nonces.expires_at < now()",
+            self.token,
+            self.nonce,
+            self.timestamp as f64,
+            max_age_seconds as f64
+        )
+        .execute(db)
+        .await?
+        .rows_affected()
+            > 0;
+
+        Ok(inserted)
+    }
+}
+
+/// Deletes the nonces of all signed requests that left the replay window, and
+/// returns how many were deleted.
+pub async fn delete_expired_nonces(db: &PgPool) -> Result<u64, sqlx::Error> {
+    let deleted = sqlx::query!("delete from request_nonces where expires_at < now()")
+        .execute(db)
+        .await?
+        .rows_affected();
+
+    Ok(deleted)
+}
+
+/// Middleware function that reads the body of signed requests, and adds its
+/// digest to the request's extensions, so [ExtractSignedToken] can verify the
+/// signature without consuming the body. Unsigned requests are passed through
+/// untouched.
+pub async fn digest_signed_body(req: Request, next: Next) -> Result<Response, ResponseError> {
+    if !RequestSignature::is_signed(req.headers()) {
+        return Ok(next.run(req).await);
+    }
+
+    let (mut parts, body) = req.into_parts();
+    let body = axum::body::to_bytes(body, MAX_SIGNED_BODY_SIZE)
+        .await
+        .map_err(|_| ResponseError::PayloadTooLarge())?;
+    parts
+        .extensions
+        .insert(BodyDigest(hex::encode(Sha256::digest(&body))));
+
+    Ok(next.run(Request::from_parts(parts, Body::from(body))).await)
+}
+
+#[derive(Debug)]
+pub struct ExtractSignedToken(pub Token);
+
+impl ExtractSignedToken {
+    /// Checks if a request should be handled by this extractor, instead of
+    /// looking for a bearer token.
+    pub fn is_signed(parts: &Parts) -> bool {
+        RequestSignature::is_signed(&parts.headers)
+    }
+}
+
+impl<S> FromRequestParts<S> for ExtractSignedToken
+where
+    AppState: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = ResponseError;
+
+    /// Extracts a valid token from a signed request. On top of what
+    /// [crate::entities::ExtractValidToken] checks, the signature has to match,
+    /// the timestamp has to be within the replay window, and the nonce can't
+    /// have been used by the same token before. The token's usage is only
+    /// recorded once the signature is verified. Malformed headers,
+    /// unknown tokens and wrong signatures count towards the client address's
+    /// lockout.
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let app_state = AppState::from_ref(state);
+        let max_age = app_state.settings.signature_max_age;
+        let ExtractClientAddr(client_addr) =
+            ExtractClientAddr::from_request_parts(parts, state).await?;
+        app_state.rate_limiter.check_ip(client_addr.ip)?;
+
+        let Some(signature) = RequestSignature::parse(&parts.headers) else {
+            warn!("signed request with a malformed authorization header");
+            app_state
+                .rate_limiter
+                .record_invalid_token(&app_state.database, client_addr.ip)
+                .await?;
+            return Err(Self::Rejection::Unauthorized());
+        };
+
+        if (Utc::now().timestamp() - signature.timestamp).abs() > max_age {
+            warn!(
+                "signed request for token=`{}` outside of the replay window",
+                signature.token
+            );
+            return Err(Self::Rejection::Unauthorized());
+        }
+
+        let body_digest = parts
+            .extensions
+            .get::<BodyDigest>()
+            .map(|digest| digest.0.clone())
+            .ok_or_else(|| anyhow::anyhow!("body digest missing on signed request"))?;
+
+        let token = Token::try_query_with_uuid(&app_state.database, signature.token).await?;
+        let Some((token, token_value)) = token else {
+            warn!("signed request for unknown token=`{}`", signature.token);
+            app_state
+                .rate_limiter
+                .record_invalid_token(&app_state.database, client_addr.ip)
+                .await?;
+            return Err(Self::Rejection::Unauthorized());
+        };
+
+        if !signature.verify(parts, &body_digest, &token_value) {
+            warn!("invalid signature for token=`{}`", token.uuid);
+            app_state
+                .rate_limiter
+                .record_invalid_token(&app_state.database, client_addr.ip)
+                .await?;
+            return Err(Self::Rejection::Unauthorized());
+        }
+
+        let user_agent = parts
+            .headers
+            .get(header::USER_AGENT)
+            .and_then(|value| value.to_str().ok());
+        token
+            .check_usable(&app_state.database, client_addr.ip, user_agent)
+            .await?;
+
+        if !signature
+            .remember_nonce(&app_state.database, max_age)
+            .await?
+        {
+            warn!("replayed signed request for token=`{}`", token.uuid);
+            return Err(Self::Rejection::Unauthorized());
+        }
+
+        app_state.rate_limiter.check_token(token.uuid)?;
+
+        Ok(Self(token))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{HeaderValue, Request};
+
+    use super::*;
+
+    const TOKEN: &str = "8a7b2c9e-1f3d-4e5a-9b6c-7d8e9f0a1b2c";
+
+    fn headers(authorization: &str) -> HeaderMap {
+        HeaderMap::from_iter([(
+            header::AUTHORIZATION,
+            HeaderValue::from_str(authorization).unwrap(),
+        )])
+    }
+
+    fn parts(method: &str, uri: &str) -> Parts {
+        Request::builder()
+            .method(method)
+            .uri(uri)
+            .body(())
+            .unwrap()
+            .into_parts()
+            .0
+    }
+
+    fn sign(string_to_sign: &str, key: &str) -> String {
+        let mut mac = Hmac::<Sha256>::new_from_slice(key.as_bytes()).unwrap();
+        mac.update(string_to_sign.as_bytes());
+        hex::encode(mac.finalize().into_bytes())
+    }
+
+    #[test]
+    fn detects_signed_requests() {
+        assert!(RequestSignature::is_signed(&headers(
+            "VSSV-HMAC-SHA256 token=x"
+        )));
+        assert!(!RequestSignature::is_signed(&headers("Bearer abc")));
+        assert!(!RequestSignature::is_signed(&HeaderMap::new()));
+    }
+
+    #[test]
+    fn parses_headers() {
+        let signature = RequestSignature::parse(&headers(&format!(
+            "VSSV-HMAC-SHA256 token={}, timestamp=1700000000,nonce=abc,signature=00ff",
+            TOKEN
+        )))
+        .unwrap();
+        assert_eq!(signature.token.to_string(), TOKEN);
+        assert_eq!(signature.timestamp, 1_700_000_000);
+        assert_eq!(signature.nonce, "abc");
+        assert_eq!(signature.signature, vec![0x00, 0xff]);
+    }
+
+    #[test]
+    fn rejects_malformed_headers() {
+        let long_nonce = "n".repeat(MAX_NONCE_LENGTH + 1);
+        for params in [
+            "timestamp=1,nonce=abc,signature=00".to_string(),
+            format!("token={},nonce=abc,signature=00", TOKEN),
+            format!("token={},timestamp=1,signature=00", TOKEN),
+            format!("token={},timestamp=1,nonce=abc", TOKEN),
+            format!("token={},timestamp=1,nonce=,signature=00", TOKEN),
+            format!(
+                "token={},timestamp=1,nonce={},signature=00",
+                TOKEN, long_nonce
+            ),
+            format!("token={},timestamp=1,nonce=abc,signature=zz", TOKEN),
+            "token=nope,timestamp=1,nonce=abc,signature=00".to_string(),
+            format!("token={},timestamp=soon,nonce=abc,signature=00", TOKEN),
+            format!("token={},timestamp=1,nonce=abc,signature=00,extra=1", TOKEN),
+            format!("token={},timestamp=1,nonce=abc,signature", TOKEN),
+        ] {
+            assert!(
+                RequestSignature::parse(&headers(&format!("VSSV-HMAC-SHA256 {}", params)))
+                    .is_none(),
+                "{}",
+                params
+            );
+        }
+
+        assert!(
+            RequestSignature::parse(&headers(&format!(
+                "VSSV-HMAC-SHA256token={},timestamp=1,nonce=abc,signature=00",
+                TOKEN
+            )))
+            .is_none()
+        );
+    }
+
+    #[test]
+    fn verifies_signatures() {
+        let parts = parts("POST", "/secret/abc/contents?x=1");
+        let digest = hex::encode(Sha256::digest(b"body"));
+        let string_to_sign = format!(
+            "POST\n/secret/abc/contents?x=1\n1700000000\nabc\n{}",
+            digest
+        );
+        let mut signature = RequestSignature {
+            token: TOKEN.parse().unwrap(),
+            timestamp: 1_700_000_000,
+            nonce: "abc".to_string(),
+            signature: hex::decode(sign(&string_to_sign, "token value")).unwrap(),
+        };
+        assert_eq!(signature.string_to_sign(&parts, &digest), string_to_sign);
+        assert!(signature.verify(&parts, &digest, "token value"));
+
+        assert!(!signature.verify(&parts, &digest, "other token value"));
+        assert!(!signature.verify(&parts, &hex::encode(Sha256::digest(b"")), "token value"));
+        assert!(!signature.verify(
+            &self::parts("PUT", "/secret/abc/contents?x=1"),
+            &digest,
+            "token value"
+        ));
+        assert!(!signature.verify(
+            &self::parts("POST", "/secret/abc/contents?x=2"),
+            &digest,
+            "token value"
+        ));
+
+        signature.nonce = "abd".to_string();
+        assert!(!signature.verify(&parts, &digest, "token value"));
+    }
+}
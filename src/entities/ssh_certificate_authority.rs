@@ -0,0 +1,317 @@
+use anyhow::Context;
+use chrono::{DateTime, Duration, Utc};
+use rand::{RngCore, rngs::OsRng};
+use serde::{Deserialize, Serialize};
+use sqlx::PgExecutor;
+use ssh_key::{HashAlg, PrivateKey, PublicKey, certificate};
+use uuid::Uuid;
+
+use crate::{entities::Secret, errors::ResponseError};
+
+/// The extensions OpenSSH's `ssh-keygen` adds to user certificates by default.
+/// Superuser tokens get these unless they ask for something else.
+const DEFAULT_USER_EXTENSIONS: &[&str] = &[
+    "permit-X11-forwarding",
+    "permit-agent-forwarding",
+    "permit-port-forwarding",
+    "permit-pty",
+    "permit-user-rc",
+];
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "ssh_certificate_type", rename_all = "snake_case")]
+pub enum SshCertificateType {
+    #[default]
+    User,
+    Host,
+}
+
+/// An SSH certificate authority. The CA's private key is stored in a secret.
+#[derive(Debug)]
+pub struct SshCertificateAuthority {
+    pub uuid: Uuid,
+    pub secret: Uuid,
+    pub max_ttl_seconds: i64,
+}
+
+/// What a token is allowed to get signed, as determined by the policies of its
+/// roles.
+#[derive(Debug)]
+pub struct SshGrant {
+    pub max_ttl_seconds: i64,
+    pub extensions: Vec<String>,
+}
+
+/// A freshly signed SSH certificate.
+#[derive(Debug)]
+pub struct SignedSshCertificate {
+    pub certificate: String,
+    pub serial: u64,
+    pub fingerprint: String,
+    pub not_after: DateTime<Utc>,
+}
+
+impl SshCertificateAuthority {
+    /// Tries to find an SSH CA by its name. If nothing is found, it will result
+    /// with None().
+    pub async fn try_find_by_name<'e>(
+        db: impl PgExecutor<'e>,
+        name: &str,
+    ) -> Result<Option<Self>, sqlx::Error> {
+        sqlx::query_as!(
+            Self,
+            r#"select uuid, secret, extract(epoch from max_ttl)::bigint as "max_ttl_seconds!"
+            from ssh_certificate_authorities where name = $1"#,
+            name
+        )
+        .fetch_optional(db)
+        .await
+    }
+
+    /// Finds the policy that allows a token to get a certificate of the given
+    /// type for all the given principals. If `extensions` is set, the policy
+    /// also has to allow all of them, otherwise all extensions allowed by the
+    /// policy are granted. If multiple policies match, the one with the longest
+    /// TTL wins, which is capped by the CA's own maximum. Returns None() if no
+    /// policy matches, which is never the case for superuser tokens.
+    pub async fn grant_for<'e>(
+        &self,
+        db: impl PgExecutor<'e>,
+        token_uuid: Uuid,
+        superuser: bool,
+        certificate_type: SshCertificateType,
+        principals: &[String],
+        extensions: Option<&[String]>,
+    ) -> Result<Option<SshGrant>, sqlx::Error> {
+        if superuser {
+            let default_extensions = match certificate_type {
+                SshCertificateType::User => DEFAULT_USER_EXTENSIONS,
+                SshCertificateType::Host => &[],
+            };
+            return Ok(Some(SshGrant {
+                max_ttl_seconds: self.max_ttl_seconds,
+                extensions: extensions
+                    .map(<[String]>::to_vec)
+                    .unwrap_or_else(|| default_extensions.iter().map(|e| e.to_string()).collect()),
+            }));
+        }
+
+        let policies = sqlx::query!(
+            r#"select
+              p.allowed_principals, p.allowed_extensions,
+              extract(epoch from p.max_ttl)::bigint as "max_ttl_seconds!"
+            from token_roles tr
+            join role_ssh_policies p on p.role = tr.role
+            where tr.token = $1 and p.authority = $2 and p.certificate_type = $3"#,
+            token_uuid,
+            self.uuid,
+            certificate_type as SshCertificateType
+        )
+        .fetch_all(db)
+        .await?;
+
+        Ok(policies
+            .into_iter()
+            .filter(|policy| {
+                principals.iter().all(|principal| {
+                    policy
+                        .allowed_principals
+                        .iter()
+                        .any(|pattern| principal_matches(pattern, principal))
+                }) && extensions.is_none_or(|extensions| {
+                    extensions
+                        .iter()
+                        .all(|extension| policy.allowed_extensions.contains(extension))
+                })
+            })
+            .max_by_key(|policy| policy.max_ttl_seconds)
+            .map(|policy| SshGrant {
+                max_ttl_seconds: policy.max_ttl_seconds.min(self.max_ttl_seconds),
+                extensions: extensions
+                    .map(<[String]>::to_vec)
+                    .unwrap_or(policy.allowed_extensions),
+            }))
+    }
+
+    /// Loads the CA's private key from its secret. The secret's contents have
+    /// to be an unencrypted private key in the OpenSSH format.
+    pub fn load_private_key(secret: &Secret) -> Result<PrivateKey, ResponseError> {
+        let key = PrivateKey::from_openssh(secret.contents.as_deref().unwrap_or_default())
+            .context("invalid SSH CA private key")?;
+        if key.is_encrypted() {
+            return Err(
+                anyhow::anyhow!("SSH CA private key `{}` is encrypted", secret.uuid).into(),
+            );
+        }
+
+        Ok(key)
+    }
+}
+
+impl SshGrant {
+    /// Signs a public key with the CA's private key. The principals and the TTL
+    /// have to be checked beforehand, the extensions are taken from the grant.
+    pub fn sign(
+        &self,
+        ca_key: &PrivateKey,
+        public_key: &PublicKey,
+        certificate_type: SshCertificateType,
+        key_id: String,
+        principals: &[String],
+        ttl_seconds: i64,
+    ) -> Result<SignedSshCertificate, ResponseError> {
+        let not_before = Utc::now();
+        let not_after = not_before + Duration::seconds(ttl_seconds);
+        let serial = OsRng.next_u64();
+
+        let mut builder = certificate::Builder::new_with_random_nonce(
+            &mut OsRng,
+            public_key.key_data().clone(),
+            not_before
+                .timestamp()
+                .try_into()
+                .context("invalid timestamp")?,
+            not_after
+                .timestamp()
+                .try_into()
+                .context("invalid timestamp")?,
+        )
+        .context("could not create certificate")?;
+        builder
+            .serial(serial)
+            .and_then(|b| {
+                b.cert_type(match certificate_type {
+                    SshCertificateType::User => certificate::CertType::User,
+                    SshCertificateType::Host => certificate::CertType::Host,
+                })
+            })
+            .and_then(|b| b.key_id(key_id))
+            .map_err(|e| ResponseError::InvalidCertificateRequest(e.to_string()))?;
+        for principal in principals {
+            builder
+                .valid_principal(principal)
+                .map_err(|e| ResponseError::InvalidCertificateRequest(e.to_string()))?;
+        }
+        for extension in &self.extensions {
+            builder
+                .extension(extension, "")
+                .map_err(|e| ResponseError::InvalidCertificateRequest(e.to_string()))?;
+        }
+
+        let certificate = builder.sign(ca_key).context("could not sign certificate")?;
+
+        Ok(SignedSshCertificate {
+            certificate: certificate
+                .to_openssh()
+                .context("could not encode certificate")?,
+            serial,
+            fingerprint: public_key.fingerprint(HashAlg::Sha256).to_string(),
+            not_after,
+        })
+    }
+}
+
+/// Checks if a principal is allowed by a policy pattern. `*` allows all
+/// principals, and `*.example.com` allows everything below `example.com`,
+/// which is mostly useful for host certificates. Everything else has to
+/// match exactly.
+fn principal_matches(pattern: &str, principal: &str) -> bool {
+    match pattern.strip_prefix('*') {
+        Some("") => true,
+        Some(suffix) if suffix.starts_with('.') => {
+            principal.len() > suffix.len() && principal.ends_with(suffix)
+        }
+        _ => pattern == principal,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ssh_key::{Certificate, private::Ed25519Keypair};
+
+    use super::*;
+
+    fn key(seed: u8) -> PrivateKey {
+        PrivateKey::from(Ed25519Keypair::from_seed(&[seed; 32]))
+    }
+
+    #[test]
+    fn matches_principals() {
+        assert!(principal_matches("*", "root"));
+        assert!(principal_matches("deploy", "deploy"));
+        assert!(!principal_matches("deploy", "Deploy"));
+        assert!(!principal_matches("deploy", "deployer"));
+        assert!(principal_matches("*.example.com", "web.example.com"));
+        assert!(!principal_matches("*.example.com", "example.com"));
+        assert!(!principal_matches("*.example.com", "evilexample.com"));
+        assert!(!principal_matches("*example.com", "evilexample.com"));
+    }
+
+    #[test]
+    fn loads_private_keys() {
+        let openssh = key(1).to_openssh(ssh_key::LineEnding::LF).unwrap();
+        let secret = Secret {
+            uuid: Uuid::nil(),
+            kind: crate::entities::SecretKind::Blob,
+            file_name: None,
+            contents: Some(openssh.as_bytes().to_vec()),
+            fields: None,
+            requires_approval: false,
+        };
+        assert_eq!(
+            SshCertificateAuthority::load_private_key(&secret)
+                .unwrap()
+                .public_key(),
+            key(1).public_key()
+        );
+
+        let secret = Secret {
+            contents: Some(b"not a key".to_vec()),
+            ..secret
+        };
+        assert!(SshCertificateAuthority::load_private_key(&secret).is_err());
+    }
+
+    #[test]
+    fn signs_certificates() {
+        let ca_key = key(1);
+        let user_key = key(2);
+        let grant = SshGrant {
+            max_ttl_seconds: 3600,
+            extensions: vec!["permit-pty".to_string()],
+        };
+        let signed = grant
+            .sign(
+                &ca_key,
+                user_key.public_key(),
+                SshCertificateType::User,
+                "alice@laptop".to_string(),
+                &["alice".to_string(), "deploy".to_string()],
+                600,
+            )
+            .unwrap();
+
+        let certificate = Certificate::from_openssh(&signed.certificate).unwrap();
+        certificate
+            .validate([&ca_key.public_key().fingerprint(HashAlg::Sha256)])
+            .unwrap();
+        assert_eq!(certificate.public_key(), user_key.public_key().key_data());
+        assert_eq!(certificate.cert_type(), certificate::CertType::User);
+        assert_eq!(certificate.serial(), signed.serial);
+        assert_eq!(certificate.key_id(), "alice@laptop");
+        assert_eq!(certificate.valid_principals(), ["alice", "deploy"]);
+        assert_eq!(
+            certificate.extensions().keys().collect::<Vec<_>>(),
+            ["permit-pty"]
+        );
+        assert_eq!(certificate.valid_before() - certificate.valid_after(), 600);
+        assert_eq!(
+            signed.fingerprint,
+            user_key
+                .public_key()
+                .fingerprint(HashAlg::Sha256)
+                .to_string()
+        );
+    }
+}
@@ -0,0 +1,52 @@
+use chrono::{Duration, Utc};
+use serde::Serialize;
+use sqlx::PgExecutor;
+
+use crate::entities::{MAX_USAGE_DAYS, TokenUsage};
+
+/// Tokens that deserve a closer look, grouped by why they do. A token can show
+/// up in multiple groups.
+#[derive(Debug, Serialize)]
+pub struct StaleTokenReport {
+    /// Tokens that have never been used.
+    pub never_used: Vec<TokenUsage>,
+    /// Tokens that have been used, but not in the last `idle_days` days.
+    pub unused: Vec<TokenUsage>,
+    /// Tokens that expire within the next `expiring_within_days` days.
+    pub expiring_soon: Vec<TokenUsage>,
+    /// Tokens that have been used after they expired.
+    pub expired_but_used: Vec<TokenUsage>,
+    /// All superuser tokens, as they can do anything.
+    pub superuser: Vec<TokenUsage>,
+}
+
+impl StaleTokenReport {
+    /// Builds the report from the usage of all tokens. The request counts in
+    /// the report cover the last `idle_days` days.
+    pub async fn build<'e>(
+        db: impl PgExecutor<'e>,
+        idle_days: i32,
+        expiring_within_days: i32,
+    ) -> Result<Self, sqlx::Error> {
+        let idle_days = idle_days.clamp(1, MAX_USAGE_DAYS);
+        let tokens = TokenUsage::list(db, idle_days).await?;
+
+        let now = Utc::now();
+        let idle_since = now - Duration::days(idle_days.into());
+        let expiring_before = now + Duration::days(expiring_within_days.max(0).into());
+        let select = |filter: &dyn Fn(&TokenUsage) -> bool| -> Vec<TokenUsage> {
+            tokens.iter().filter(|t| filter(t)).cloned().collect()
+        };
+
+        Ok(Self {
+            never_used: select(&|t| t.used_at.is_none()),
+            unused: select(&|t| t.used_at.is_some_and(|used_at| used_at < idle_since)),
+            expiring_soon: select(&|t| {
+                t.expires_at
+                    .is_some_and(|expires_at| expires_at > now && expires_at <= expiring_before)
+            }),
+            expired_but_used: select(&|t| t.expired_used_at.is_some()),
+            superuser: select(&|t| t.superuser),
+        })
+    }
+}
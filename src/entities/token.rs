@@ -1,24 +1,58 @@
+use std::net::IpAddr;
+
 use axum::{
     extract::{FromRef, FromRequestParts},
-    http::request::Parts,
+    http::{header, request::Parts},
 };
 use axum_extra::{
     TypedHeader,
     headers::{Authorization, authorization::Bearer},
 };
-use chrono::{DateTime, Utc};
-use sqlx::{PgExecutor, postgres::PgQueryResult};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::{PgConnection, PgExecutor, PgPool, postgres::PgQueryResult};
 use tracing::{info, warn};
 use uuid::Uuid;
 
-use crate::{AppState, errors::ResponseError};
+use crate::{
+    AppState,
+    entities::{
+        AuditLogAction, AuditLogEntry, ExtractClientAddr, ExtractSignedToken, TransitPermissions,
+    },
+    errors::ResponseError,
+};
+
+/// The longest TTL a child token can have, in seconds.
+const MAX_CHILD_TTL: i64 = 24 * 60 * 60;
 
-/// An access token stored in the database.
+/// User agents are cut off after this many characters before they're stored.
+const MAX_USER_AGENT_LENGTH: usize = 256;
+
+/// An access token stored in the database. For child tokens, `expires_at` is
+/// the earliest expiration of the token and all its ancestors, and `suspended`
+/// and `outside_schedule` are set if they apply to the token or any of its
+/// ancestors.
 #[derive(Debug)]
 pub struct Token {
     pub uuid: Uuid,
+    pub parent: Option<Uuid>,
     pub expires_at: Option<DateTime<Utc>>,
+    pub suspended: bool,
+    pub outside_schedule: bool,
     pub superuser: bool,
+    pub can_approve: bool,
+    pub break_glass: bool,
+    pub require_signed_requests: bool,
+    pub notes: Option<String>,
+}
+
+/// Why a token was denied access, as stored in the audit log.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DenialReason {
+    NoPermission,
+    OutsideSchedule,
 }
 
 impl Token {
@@ -30,13 +64,63 @@ impl Token {
     ) -> Result<Option<Self>, sqlx::Error> {
         sqlx::query_as!(
             Self,
-            "select uuid, expires_at, superuser from tokens where token = $1",
+            r#"select
+              t.uuid, t.parent, t.superuser, t.can_approve, t.break_glass, t.require_signed_requests,
+              t.notes,
+              (
+                select min(x.expires_at) from tokens x
+                where x.uuid = t.uuid
+                  or x.uuid in (select ancestor from token_ancestors where token = t.uuid)
+              ) as expires_at,
+              exists (
+                select 1 from tokens x
+                where x.suspended_at is not null and (
+                  x.uuid = t.uuid
+                  or x.uuid in (select ancestor from token_ancestors where token = t.uuid)
+                )
+              ) as "suspended!",
+              exists (
+                select 1 from tokens x
+                where not access_schedule_open(x.access_schedule) and (
+                  x.uuid = t.uuid
+                  or x.uuid in (select ancestor from token_ancestors where token = t.uuid)
+                )
+              ) as "outside_schedule!"
+            from tokens t where t.token = $1"#,
             token
         )
         .fetch_optional(db)
         .await
     }
 
+    /// Tries to find a Token by its UUID, for verifying signed requests.
+    /// Returns the Token together with its token value, which is the key the
+    /// requests are signed with. The Token itself is loaded via
+    /// [Self::try_query_with_token], so both lookups always agree on its
+    /// expiration, suspension, and schedule. If nothing is found, it will
+    /// result with None().
+    pub async fn try_query_with_uuid(
+        db: &PgPool,
+        uuid: Uuid,
+    ) -> Result<Option<(Self, String)>, sqlx::Error> {
+        let token_value = sqlx::query_scalar!("select token from tokens where uuid = $1", uuid)
+            .fetch_optional(db)
+            .await?;
+        let Some(token_value) = token_value else {
+            return Ok(None);
+        };
+
+        Ok(Self::try_query_with_token(db, &token_value)
+            .await?
+            .map(|token| (token, token_value)))
+    }
+
+    /// Checks if this Token can approve access requests for secrets that
+    /// require approval. Superuser tokens always can.
+    pub fn is_approver(&self) -> bool {
+        self.superuser || self.can_approve
+    }
+
     /// Little helper that checks if a token is expired. If the token has no
     /// expiration date, it will always return `false`.
     pub fn is_expired(&self) -> bool {
@@ -46,26 +130,237 @@ impl Token {
         }
     }
 
-    /// Updates the used_at timestamp in the database. This should be called
-    /// early in the chain, as soon as the token is validated to be existing -
-    /// even if it's expired. This allows tracking expired tokens that are still
-    /// used.
-    pub async fn update_used_timestamp<'e>(
-        &mut self,
+    /// Records the token's usage, and checks if it can be used right now: it
+    /// must neither be expired nor suspended, and it has to be within its
+    /// access schedule. Requests outside of the schedule are logged in the
+    /// audit log. This is shared by all ways of authenticating a token, so
+    /// they can't disagree on what a usable token is.
+    pub async fn check_usable(
+        &self,
+        db: &PgPool,
+        client_addr: IpAddr,
+        user_agent: Option<&str>,
+    ) -> Result<(), ResponseError> {
+        let _ = self.record_usage(db, client_addr, user_agent).await?;
+
+        if self.is_expired() {
+            warn!("use of expired token=`{}`", self.uuid);
+            return Err(ResponseError::Unauthorized());
+        }
+
+        if self.suspended {
+            warn!("use of suspended token=`{}`", self.uuid);
+            return Err(ResponseError::Unauthorized());
+        }
+
+        if self.outside_schedule {
+            warn!(
+                "use of token=`{}` outside of its access schedule",
+                self.uuid
+            );
+            let _ = AuditLogEntry::log_token_action(
+                db,
+                client_addr,
+                AuditLogAction::AccessDenied,
+                self.uuid,
+                json!({ "reason": DenialReason::OutsideSchedule }),
+            )
+            .await?;
+            return Err(ResponseError::Unauthorized());
+        }
+
+        Ok(())
+    }
+
+    /// Records that the token was used: the used_at timestamp, the client's
+    /// address and user agent, and the number of requests on the current day
+    /// get updated. This should be called early in the chain, as soon as the
+    /// token is validated to be existing - even if it's expired. This allows
+    /// tracking expired tokens that are still used, and the first use after
+    /// the expiration is remembered as expired_used_at.
+    pub async fn record_usage<'e>(
+        &self,
         db: impl PgExecutor<'e>,
+        client_addr: IpAddr,
+        user_agent: Option<&str>,
     ) -> Result<PgQueryResult, sqlx::Error> {
+        let user_agent =
+            user_agent.map(|ua| ua.chars().take(MAX_USER_AGENT_LENGTH).collect::<String>());
         sqlx::query!(
-            "update tokens set used_at = $1 where uuid = $2",
-            Utc::now(),
-            self.uuid
+            r#"with used as (
+              update tokens set
+                used_at = now(),
+                last_client_addr = $2,
+                last_user_agent = $3,
+                expired_used_at = case when $4 then coalesce(expired_used_at, now()) else expired_used_at end
+              where uuid = $1
+            )
+            insert into token_usage (token, day, requests)
+            values ($1, (now() at time zone 'utc')::date, 1)
+            on conflict (token, day) do update set requests = token_usage.requests + 1"#,
+            self.uuid,
+            client_addr.to_canonical() as IpAddr,
+            user_agent,
+            self.is_expired()
+        )
+        .execute(db)
+        .await
+    }
+
+    /// Returns the longest TTL (in seconds) a child of this Token can have. A
+    /// child can't outlive its parent.
+    pub fn max_child_ttl(&self) -> i64 {
+        match self.expires_at {
+            None => MAX_CHILD_TTL,
+            Some(expiry) => (expiry - Utc::now()).num_seconds().min(MAX_CHILD_TTL),
+        }
+    }
+
+    /// Creates a child token that expires after `ttl_seconds` and has the given
+    /// permissions on secrets. The caller has to make sure that this Token is
+    /// allowed to do everything the child can do. Returns the new Token, and
+    /// its token value.
+    pub async fn create_child(
+        &self,
+        conn: &mut PgConnection,
+        ttl_seconds: i64,
+        notes: Option<&str>,
+        grants: &[SecretGrant],
+    ) -> Result<(Self, String), ResponseError> {
+        let child = sqlx::query!(
+            "insert into tokens (parent, expires_at, notes) values ($1, $2, $3) returning uuid, token",
+            self.uuid,
+            Utc::now() + Duration::seconds(ttl_seconds),
+            notes
+        )
+        .fetch_one(&mut *conn)
+        .await?;
+
+        for grant in grants {
+            let result = sqlx::query!(
+                "insert into token_permissions (token, secret, can_read, can_write) select $1, uuid, $3, $4 from secrets where uuid = $2",
+                child.uuid,
+                grant.secret,
+                grant.can_read,
+                grant.can_write
+            )
+            .execute(&mut *conn)
+            .await?;
+            if result.rows_affected() == 0 {
+                return Err(ResponseError::NotFoundError());
+            }
+        }
+
+        let token = Self::try_query_with_token(&mut *conn, &child.token)
+            .await?
+            .ok_or(ResponseError::NotFoundError())?;
+        Ok((token, child.token))
+    }
+
+    /// Creates a token for a JWT login. The token expires after `ttl_seconds`,
+    /// and gets the given roles assigned. Returns the new Token, and its token
+    /// value.
+    pub async fn create_for_jwt(
+        conn: &mut PgConnection,
+        jwt_issuer: Uuid,
+        ttl_seconds: i64,
+        notes: &str,
+        roles: &[Uuid],
+    ) -> Result<(Self, String), ResponseError> {
+        let created = sqlx::query!(
+            "insert into tokens (jwt_issuer, expires_at, notes) values ($1, $2, $3) returning uuid, token",
+            jwt_issuer,
+            Utc::now() + Duration::seconds(ttl_seconds),
+            notes
+        )
+        .fetch_one(&mut *conn)
+        .await?;
+
+        sqlx::query!(
+            "insert into token_roles (token, role) select $1, unnest($2::uuid[]) on conflict do nothing",
+            created.uuid,
+            roles
+        )
+        .execute(&mut *conn)
+        .await?;
+
+        let token = Self::try_query_with_token(&mut *conn, &created.token)
+            .await?
+            .ok_or(ResponseError::NotFoundError())?;
+        Ok((token, created.token))
+    }
+
+    /// Suspends a token, so it and all its children get rejected until it's
+    /// unsuspended. Suspending a suspended token only updates the reason.
+    /// Returns `false` if the token doesn't exist.
+    pub async fn suspend<'e>(
+        db: impl PgExecutor<'e>,
+        uuid: Uuid,
+        reason: &str,
+    ) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query!(
+            "update tokens set suspended_at = coalesce(suspended_at, now()), suspended_reason = $2 where uuid = $1",
+            uuid,
+            reason
+        )
+        .execute(db)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Lifts a token's suspension. Returns `false` if the token doesn't exist.
+    pub async fn unsuspend<'e>(db: impl PgExecutor<'e>, uuid: Uuid) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query!(
+            "update tokens set suspended_at = null, suspended_reason = null where uuid = $1",
+            uuid
         )
         .execute(db)
+        .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Suspends all tokens that haven't been used in the last `idle_days` days,
+    /// or that were never used and are older than that. Expired tokens are
+    /// left alone, and so are superuser tokens, as only superusers can
+    /// unsuspend tokens again. Returns the UUIDs of the suspended tokens.
+    pub async fn suspend_idle<'e>(
+        db: impl PgExecutor<'e>,
+        idle_days: i32,
+    ) -> Result<Vec<Uuid>, sqlx::Error> {
+        sqlx::query_scalar!(
+            r#"update tokens set suspended_at = now(), suspended_reason = 'idle'
+            where suspended_at is null
+              and not superuser
+              and coalesce(used_at, created_at) < now() - make_interval(days => $1)
+              and (expires_at is null or expires_at > now())
+            returning uuid"#,
+            idle_days
+        )
+        .fetch_all(db)
+        .await
+    }
+
+    /// Returns the names of all roles assigned to this Token, sorted by name.
+    pub async fn role_names<'e>(
+        &self,
+        db: impl PgExecutor<'e>,
+    ) -> Result<Vec<String>, sqlx::Error> {
+        sqlx::query_scalar!(
+            "select r.name from token_roles tr join roles r on r.uuid = tr.role where tr.token = $1 order by r.name",
+            self.uuid
+        )
+        .fetch_all(db)
         .await
     }
 
     /// Checks if this Token can read a given secret (looked up by the secret's
     /// UUID). This always returns `true` it the token is a superuser token.
-    /// Otherwise, it checks that a token_permission with can_read=true exists.
+    /// Otherwise, it checks that a permission with can_read=true exists, either
+    /// for the secret itself or for a folder containing it, granted directly or
+    /// via one of the token's roles, and that none of those permissions is a
+    /// deny.
     pub async fn can_read_secret<'e>(
         &self,
         db: impl PgExecutor<'e>,
@@ -75,16 +370,23 @@ impl Token {
             return Ok(true);
         }
 
-        Ok(sqlx::query!(
-            r#"select token from token_permissions where token = $1 and secret = $2 and can_read = true"#,
+        sqlx::query_scalar!(
+            r#"select exists(
+              select 1 from token_secret_access where token = $1 and secret = $2 and can_read = true
+            ) as "allowed!""#,
             self.uuid,
             secret_uuid
-        ).fetch_optional(db).await?.is_some())
+        )
+        .fetch_one(db)
+        .await
     }
 
     /// Checks if this Token can write a given secret (looked up by the secret's
     /// UUID). This always returns `true` it the token is a superuser token.
-    /// Otherwise, it checks that a token_permission with can_write=true exists.
+    /// Otherwise, it checks that a permission with can_write=true exists, either
+    /// for the secret itself or for a folder containing it, granted directly or
+    /// via one of the token's roles, and that none of those permissions is a
+    /// deny.
     pub async fn can_write_secret<'e>(
         &self,
         db: impl PgExecutor<'e>,
@@ -94,12 +396,82 @@ impl Token {
             return Ok(true);
         }
 
-        Ok(sqlx::query!(
-            r#"select token from token_permissions where token = $1 and secret = $2 and can_write = true"#,
+        sqlx::query_scalar!(
+            r#"select exists(
+              select 1 from token_secret_access where token = $1 and secret = $2 and can_write = true
+            ) as "allowed!""#,
             self.uuid,
             secret_uuid
-        ).fetch_optional(db).await?.is_some())
+        )
+        .fetch_one(db)
+        .await
     }
+
+    /// Explains why this Token can't read or write a given secret: either it
+    /// would be allowed, but not right now because of an access schedule, or it
+    /// has no permission at all. This should only be called after a denied
+    /// check.
+    pub async fn secret_denial_reason<'e>(
+        &self,
+        db: impl PgExecutor<'e>,
+        secret_uuid: Uuid,
+        write: bool,
+    ) -> Result<DenialReason, sqlx::Error> {
+        let allowed_any_time = sqlx::query_scalar!(
+            r#"select exists(
+              select 1 from token_secret_access
+              where token = $1 and secret = $2
+                and case when $3 then can_write_any_time else can_read_any_time end
+            ) as "allowed!""#,
+            self.uuid,
+            secret_uuid,
+            write
+        )
+        .fetch_one(db)
+        .await?;
+
+        if allowed_any_time {
+            Ok(DenialReason::OutsideSchedule)
+        } else {
+            Ok(DenialReason::NoPermission)
+        }
+    }
+
+    /// Returns this Token's permissions on a transit key (looked up by the
+    /// key's name). Superuser tokens can do everything. Unlike secrets, transit
+    /// keys can only be granted to tokens directly.
+    pub async fn transit_permissions<'e>(
+        &self,
+        db: impl PgExecutor<'e>,
+        key_name: &str,
+    ) -> Result<TransitPermissions, sqlx::Error> {
+        if self.superuser {
+            return Ok(TransitPermissions::all());
+        }
+
+        Ok(sqlx::query_as!(
+            TransitPermissions,
+            r#"select tp.can_encrypt, tp.can_decrypt, tp.can_sign, tp.can_verify
+            from token_transit_permissions tp
+            join transit_keys k on k.uuid = tp.key
+            where tp.token = $1 and k.name = $2"#,
+            self.uuid,
+            key_name
+        )
+        .fetch_optional(db)
+        .await?
+        .unwrap_or_default())
+    }
+}
+
+/// Permissions on a single secret, as requested for a child token.
+#[derive(Debug, Deserialize)]
+pub struct SecretGrant {
+    pub secret: Uuid,
+    #[serde(default)]
+    pub can_read: bool,
+    #[serde(default)]
+    pub can_write: bool,
 }
 
 #[derive(Debug)]
@@ -113,12 +485,26 @@ where
     type Rejection = ResponseError;
 
     /// Happy little [FromRequestParts] implementation that always extracts a valid
-    /// token. "Valid" means here: it exists, and it's not expired. This
-    /// extractor will also set the token's used_at timestamp, and it does that
-    /// even when the token is expired, so there is a way to track the usage of
-    /// expired tokens.
+    /// token. "Valid" means here: it exists, and neither it nor any of its
+    /// ancestors is expired, suspended, or outside of its access schedule. This
+    /// extractor will also record the token's usage, and it does that even
+    /// when the token is expired, so there is a way to track the usage of
+    /// expired tokens. Signed requests are handed off to [ExtractSignedToken],
+    /// and tokens that require signed requests are rejected as bearer tokens.
+    /// Requests are rate limited by client address before the database is
+    /// asked, and by token afterwards. Invalid tokens count towards the client
+    /// address's lockout.
     async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        if ExtractSignedToken::is_signed(parts) {
+            let ExtractSignedToken(token) =
+                ExtractSignedToken::from_request_parts(parts, state).await?;
+            return Ok(Self(token));
+        }
+
         let app_state = AppState::from_ref(state);
+        let ExtractClientAddr(client_addr) =
+            ExtractClientAddr::from_request_parts(parts, state).await?;
+        app_state.rate_limiter.check_ip(client_addr.ip)?;
 
         let token_header = TypedHeader::<Authorization<Bearer>>::from_request_parts(parts, state)
             .await
@@ -126,18 +512,30 @@ where
             .0;
 
         let token = Token::try_query_with_token(&app_state.database, token_header.token()).await?;
-        let Some(mut token) = token else {
+        let Some(token) = token else {
             info!("use of invalid token=`{}`", token_header.token());
+            app_state
+                .rate_limiter
+                .record_invalid_token(&app_state.database, client_addr.ip)
+                .await?;
             return Err(Self::Rejection::Unauthorized());
         };
 
-        let _ = token.update_used_timestamp(&app_state.database).await?;
+        let user_agent = parts
+            .headers
+            .get(header::USER_AGENT)
+            .and_then(|value| value.to_str().ok());
+        token
+            .check_usable(&app_state.database, client_addr.ip, user_agent)
+            .await?;
 
-        if token.is_expired() {
-            warn!("use of expired token=`{}`", token.uuid);
+        if token.require_signed_requests {
+            warn!("unsigned use of token=`{}`", token.uuid);
             return Err(Self::Rejection::Unauthorized());
         }
 
+        app_state.rate_limiter.check_token(token.uuid)?;
+
         Ok(Self(token))
     }
 }
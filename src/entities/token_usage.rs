@@ -0,0 +1,107 @@
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::Serialize;
+use sqlx::PgExecutor;
+use uuid::Uuid;
+
+/// The longest period usage statistics can be requested for, in days.
+pub const MAX_USAGE_DAYS: i32 = 366;
+
+/// A token's usage statistics, as shown to superusers who want to find
+/// abandoned tokens, or tokens that are used from unexpected places. This
+/// never contains the token's value.
+#[derive(Clone, Debug, Serialize)]
+pub struct TokenUsage {
+    pub uuid: Uuid,
+    pub parent: Option<Uuid>,
+    pub notes: Option<String>,
+    pub superuser: bool,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub suspended_at: Option<DateTime<Utc>>,
+    pub suspended_reason: Option<String>,
+    pub used_at: Option<DateTime<Utc>>,
+    pub expired_used_at: Option<DateTime<Utc>>,
+    pub last_client_addr: Option<String>,
+    pub last_user_agent: Option<String>,
+    pub requests: i64,
+}
+
+/// The number of requests a token made on a single day, in UTC.
+#[derive(Debug, Serialize)]
+pub struct DailyTokenUsage {
+    pub day: NaiveDate,
+    pub requests: i64,
+}
+
+impl TokenUsage {
+    /// Lists the usage of all tokens, with the number of requests they made in
+    /// the last `days` days. Tokens that were never used, or haven't been used
+    /// for the longest time, come first.
+    pub async fn list<'e>(db: impl PgExecutor<'e>, days: i32) -> Result<Vec<Self>, sqlx::Error> {
+        sqlx::query_as!(
+            Self,
+            r#"select
+              t.uuid, t.parent, t.notes, t.superuser, t.created_at, t.expires_at,
+              t.suspended_at, t.suspended_reason, t.used_at,
+              t.expired_used_at, host(t.last_client_addr) as last_client_addr,
+              t.last_user_agent,
+              coalesce((
+                select sum(u.requests) from token_usage u
+                where u.token = t.uuid and u.day > (now() at time zone 'utc')::date - $1::int
+              ), 0)::bigint as "requests!"
+            from tokens t
+            order by t.used_at nulls first, t.created_at"#,
+            days
+        )
+        .fetch_all(db)
+        .await
+    }
+
+    /// Tries to find the usage of a single token, with the number of requests
+    /// it made in the last `days` days. If the token doesn't exist, it will
+    /// result with None().
+    pub async fn try_find<'e>(
+        db: impl PgExecutor<'e>,
+        uuid: Uuid,
+        days: i32,
+    ) -> Result<Option<Self>, sqlx::Error> {
+        sqlx::query_as!(
+            Self,
+            r#"select
+              t.uuid, t.parent, t.notes, t.superuser, t.created_at, t.expires_at,
+              t.suspended_at, t.suspended_reason, t.used_at,
+              t.expired_used_at, host(t.last_client_addr) as last_client_addr,
+              t.last_user_agent,
+              coalesce((
+                select sum(u.requests) from token_usage u
+                where u.token = t.uuid and u.day > (now() at time zone 'utc')::date - $2::int
+              ), 0)::bigint as "requests!"
+            from tokens t where t.uuid = $1"#,
+            uuid,
+            days
+        )
+        .fetch_optional(db)
+        .await
+    }
+}
+
+impl DailyTokenUsage {
+    /// Returns the number of requests per day a token made in the last `days`
+    /// days. Days without requests are left out.
+    pub async fn find_for_token<'e>(
+        db: impl PgExecutor<'e>,
+        token: Uuid,
+        days: i32,
+    ) -> Result<Vec<Self>, sqlx::Error> {
+        sqlx::query_as!(
+            Self,
+            r#"select day, requests from token_usage
+            where token = $1 and day > (now() at time zone 'utc')::date - $2::int
+            order by day"#,
+            token,
+            days
+        )
+        .fetch_all(db)
+        .await
+    }
+}
@@ -0,0 +1,339 @@
+use aes_gcm::{
+    Aes256Gcm, KeyInit,
+    aead::{Aead, AeadCore, OsRng},
+};
+use base64::{Engine, engine::general_purpose::STANDARD as BASE64};
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier};
+use serde::Serialize;
+use sqlx::PgExecutor;
+use uuid::Uuid;
+
+use crate::{entities::AuditLogAction, errors::ResponseError};
+
+const VERSIONED_PREFIX: &str = "vssv:v";
+const NONCE_LENGTH: usize = 12;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "transit_key_type", rename_all = "snake_case")]
+pub enum TransitKeyType {
+    Aes256Gcm,
+    Ed25519,
+}
+
+/// A specific version of a named transit key. The key material never leaves
+/// the server, it's only used to encrypt, decrypt, sign, and verify payloads
+/// sent by clients.
+#[derive(Debug)]
+pub struct TransitKey {
+    pub uuid: Uuid,
+    pub key_type: TransitKeyType,
+    pub version: i32,
+    key_material: Vec<u8>,
+}
+
+impl TransitKey {
+    /// Tries to find a key by its name. If no version is given, the latest
+    /// version is returned. Results in None() if either the key or the
+    /// requested version doesn't exist.
+    pub async fn try_find<'e>(
+        db: impl PgExecutor<'e>,
+        name: &str,
+        version: Option<i32>,
+    ) -> Result<Option<Self>, sqlx::Error> {
+        sqlx::query_as!(
+            Self,
+            r#"select
+              k.uuid, k.type as "key_type: TransitKeyType", v.version, v.key_material
+            from transit_keys k
+            join transit_key_versions v on v.key = k.uuid
+            where k.name = $1 and ($2::integer is null or v.version = $2)
+            order by v.version desc
+            limit 1"#,
+            name,
+            version
+        )
+        .fetch_optional(db)
+        .await
+    }
+
+    /// Encrypts a payload with AES-256-GCM and a random nonce. The result is
+    /// prefixed with the key version, so it can be decrypted after the key has
+    /// been rotated.
+    pub fn encrypt(&self, plaintext: &[u8]) -> Result<String, ResponseError> {
+        let cipher = self.cipher()?;
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let ciphertext = cipher
+            .encrypt(&nonce, plaintext)
+            .map_err(|_| ResponseError::InvalidTransitInput("encryption failed".to_string()))?;
+
+        Ok(self.versioned([&nonce[..], &ciphertext].concat()))
+    }
+
+    /// Decrypts a payload created by [Self::encrypt]. The payload has to be
+    /// passed without its version prefix, see [parse_versioned].
+    pub fn decrypt(&self, payload: &[u8]) -> Result<Vec<u8>, ResponseError> {
+        let cipher = self.cipher()?;
+        let invalid = || ResponseError::InvalidTransitInput("invalid ciphertext".to_string());
+        if payload.len() < NONCE_LENGTH {
+            return Err(invalid());
+        }
+
+        let (nonce, ciphertext) = payload.split_at(NONCE_LENGTH);
+        cipher
+            .decrypt(nonce.into(), ciphertext)
+            .map_err(|_| invalid())
+    }
+
+    /// Signs a payload with Ed25519. Like ciphertexts, the signature is prefixed
+    /// with the key version.
+    pub fn sign(&self, input: &[u8]) -> Result<String, ResponseError> {
+        let signature = self.signing_key()?.sign(input);
+        Ok(self.versioned(signature.to_bytes()))
+    }
+
+    /// Verifies a signature created by [Self::sign]. The signature has to be
+    /// passed without its version prefix, see [parse_versioned].
+    pub fn verify(&self, input: &[u8], signature: &[u8]) -> Result<bool, ResponseError> {
+        let signing_key = self.signing_key()?;
+        let Ok(signature) = Signature::from_slice(signature) else {
+            return Ok(false);
+        };
+
+        Ok(signing_key
+            .verifying_key()
+            .verify(input, &signature)
+            .is_ok())
+    }
+
+    fn cipher(&self) -> Result<Aes256Gcm, ResponseError> {
+        if self.key_type != TransitKeyType::Aes256Gcm {
+            return Err(ResponseError::TransitKeyTypeMismatch());
+        }
+
+        Ok(Aes256Gcm::new_from_slice(&self.key_material)
+            .expect("key material to be 32 bytes, as enforced by the database"))
+    }
+
+    fn signing_key(&self) -> Result<SigningKey, ResponseError> {
+        if self.key_type != TransitKeyType::Ed25519 {
+            return Err(ResponseError::TransitKeyTypeMismatch());
+        }
+
+        Ok(SigningKey::from_bytes(
+            self.key_material
+                .as_slice()
+                .try_into()
+                .expect("key material to be 32 bytes, as enforced by the database"),
+        ))
+    }
+
+    fn versioned(&self, payload: impl AsRef<[u8]>) -> String {
+        format!(
+            "{}{}:{}",
+            VERSIONED_PREFIX,
+            self.version,
+            BASE64.encode(payload)
+        )
+    }
+}
+
+/// Splits a `vssv:vVERSION:BASE64` value, as returned by [TransitKey::encrypt]
+/// and [TransitKey::sign], into the key version and the decoded payload.
+pub fn parse_versioned(value: &str) -> Result<(i32, Vec<u8>), ResponseError> {
+    let invalid = || ResponseError::InvalidTransitInput(format!("malformed value `{}`", value));
+    let (version, payload) = value
+        .strip_prefix(VERSIONED_PREFIX)
+        .and_then(|rest| rest.split_once(':'))
+        .ok_or_else(invalid)?;
+
+    let version = version.parse().map_err(|_| invalid())?;
+    let payload = BASE64.decode(payload).map_err(|_| invalid())?;
+    Ok((version, payload))
+}
+
+/// Decodes a base64-encoded payload sent by a client.
+pub fn decode_base64(field: &str, value: &str) -> Result<Vec<u8>, ResponseError> {
+    BASE64
+        .decode(value)
+        .map_err(|_| ResponseError::InvalidTransitInput(format!("`{}` must be base64", field)))
+}
+
+/// A transit key's metadata and a token's permissions on it, as shown to tokens
+/// that want to know which keys they can use. Never contains key material.
+#[derive(Debug, Serialize)]
+pub struct TransitKeySummary {
+    pub name: String,
+    pub key_type: TransitKeyType,
+    pub version: i32,
+    pub can_encrypt: bool,
+    pub can_decrypt: bool,
+    pub can_sign: bool,
+    pub can_verify: bool,
+}
+
+impl TransitKeySummary {
+    /// Lists all transit keys a token has any permission on, with the key's
+    /// latest version. Superuser tokens get to see, and use, everything.
+    pub async fn list_accessible<'e>(
+        db: impl PgExecutor<'e>,
+        token_uuid: Uuid,
+        superuser: bool,
+    ) -> Result<Vec<Self>, sqlx::Error> {
+        sqlx::query_as!(
+            Self,
+            r#"select
+              k.name, k.type as "key_type: TransitKeyType",
+              (select max(v.version) from transit_key_versions v where v.key = k.uuid) as "version!",
+              ($2 or coalesce(p.can_encrypt, false)) as "can_encrypt!",
+              ($2 or coalesce(p.can_decrypt, false)) as "can_decrypt!",
+              ($2 or coalesce(p.can_sign, false)) as "can_sign!",
+              ($2 or coalesce(p.can_verify, false)) as "can_verify!"
+            from transit_keys k
+            left join token_transit_permissions p on p.key = k.uuid and p.token = $1
+            where $2 or p.can_encrypt or p.can_decrypt or p.can_sign or p.can_verify
+            order by k.name"#,
+            token_uuid,
+            superuser
+        )
+        .fetch_all(db)
+        .await
+    }
+}
+
+/// The permissions a token has on a transit key. Tokens without any
+/// permissions for a key get the default, which allows nothing.
+#[derive(Debug, Default)]
+pub struct TransitPermissions {
+    pub can_encrypt: bool,
+    pub can_decrypt: bool,
+    pub can_sign: bool,
+    pub can_verify: bool,
+}
+
+impl TransitPermissions {
+    /// Grants everything, used for superuser tokens.
+    pub fn all() -> Self {
+        Self {
+            can_encrypt: true,
+            can_decrypt: true,
+            can_sign: true,
+            can_verify: true,
+        }
+    }
+
+    /// Checks if these permissions allow a transit action. Rewrapping needs
+    /// both encrypt and decrypt permissions, as it's a combination of both.
+    pub fn allows(&self, action: &AuditLogAction) -> bool {
+        match action {
+            AuditLogAction::TransitEncrypt => self.can_encrypt,
+            AuditLogAction::TransitDecrypt => self.can_decrypt,
+            AuditLogAction::TransitRewrap => self.can_encrypt && self.can_decrypt,
+            AuditLogAction::TransitSign => self.can_sign,
+            AuditLogAction::TransitVerify => self.can_verify,
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(key_type: TransitKeyType, version: i32, seed: u8) -> TransitKey {
+        TransitKey {
+            uuid: Uuid::nil(),
+            key_type,
+            version,
+            key_material: vec![seed; 32],
+        }
+    }
+
+    fn payload(value: &str) -> Vec<u8> {
+        parse_versioned(value).unwrap().1
+    }
+
+    #[test]
+    fn parses_versioned_values() {
+        assert_eq!(
+            parse_versioned("vssv:v3:aGVsbG8=").unwrap(),
+            (3, b"hello".to_vec())
+        );
+
+        for value in [
+            "",
+            "aGVsbG8=",
+            "vssv:v:aGVsbG8=",
+            "vssv:vx:aGVsbG8=",
+            "vssv:v3",
+            "vssv:v3:not base64",
+            "vault:v1:aGVsbG8=",
+        ] {
+            assert!(
+                matches!(
+                    parse_versioned(value),
+                    Err(ResponseError::InvalidTransitInput(_))
+                ),
+                "{}",
+                value
+            );
+        }
+    }
+
+    #[test]
+    fn encrypts_and_decrypts() {
+        let key = key(TransitKeyType::Aes256Gcm, 2, 1);
+        let ciphertext = key.encrypt(b"secret").unwrap();
+        assert!(ciphertext.starts_with("vssv:v2:"));
+        assert_ne!(key.encrypt(b"secret").unwrap(), ciphertext);
+        assert_eq!(key.decrypt(&payload(&ciphertext)).unwrap(), b"secret");
+    }
+
+    #[test]
+    fn rejects_tampered_ciphertexts() {
+        let key = key(TransitKeyType::Aes256Gcm, 1, 1);
+        let mut ciphertext = payload(&key.encrypt(b"secret").unwrap());
+        *ciphertext.last_mut().unwrap() ^= 1;
+        assert!(key.decrypt(&ciphertext).is_err());
+        assert!(key.decrypt(&[0; NONCE_LENGTH - 1]).is_err());
+
+        let other = self::key(TransitKeyType::Aes256Gcm, 1, 2);
+        let ciphertext = payload(&key.encrypt(b"secret").unwrap());
+        assert!(other.decrypt(&ciphertext).is_err());
+    }
+
+    #[test]
+    fn signs_and_verifies() {
+        let key = key(TransitKeyType::Ed25519, 1, 1);
+        let signature = payload(&key.sign(b"message").unwrap());
+        assert!(key.verify(b"message", &signature).unwrap());
+        assert!(!key.verify(b"other message", &signature).unwrap());
+        assert!(!key.verify(b"message", &signature[1..]).unwrap());
+    }
+
+    #[test]
+    fn rejects_operations_of_the_wrong_key_type() {
+        let aes = key(TransitKeyType::Aes256Gcm, 1, 1);
+        let ed25519 = key(TransitKeyType::Ed25519, 1, 1);
+        assert!(matches!(
+            aes.sign(b"message"),
+            Err(ResponseError::TransitKeyTypeMismatch())
+        ));
+        assert!(matches!(
+            ed25519.encrypt(b"secret"),
+            Err(ResponseError::TransitKeyTypeMismatch())
+        ));
+    }
+
+    #[test]
+    fn rewrap_needs_encrypt_and_decrypt() {
+        let permissions = TransitPermissions {
+            can_encrypt: true,
+            ..Default::default()
+        };
+        assert!(permissions.allows(&AuditLogAction::TransitEncrypt));
+        assert!(!permissions.allows(&AuditLogAction::TransitRewrap));
+        assert!(TransitPermissions::all().allows(&AuditLogAction::TransitRewrap));
+        assert!(!TransitPermissions::all().allows(&AuditLogAction::SecretRead));
+    }
+}
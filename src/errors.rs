@@ -14,6 +14,9 @@ use tracing::error;
 /// doing the proper formatting to show users.
 #[derive(Debug, thiserror::Error)]
 pub enum ResponseError {
+    #[error("this secret requires an approved access request")]
+    ApprovalRequired(),
+
     #[error("internal server error")]
     AxumExtensionRejection(#[from] axum::extract::rejection::ExtensionRejection),
 
@@ -26,17 +29,68 @@ pub enum ResponseError {
     #[error("x-real-ip header empty or unreadable")]
     EmptyXRealIP(#[from] axum::http::header::ToStrError),
 
+    #[error("internal server error")]
+    InternalError(#[from] anyhow::Error),
+
+    #[error("invalid certificate request: {0}")]
+    InvalidCertificateRequest(String),
+
+    #[error("invalid generator spec: {0}")]
+    InvalidGeneratorSpec(String),
+
+    #[error("break-glass justification must be between 10 and 1000 characters")]
+    InvalidJustification(),
+
+    #[error("unknown secret format")]
+    InvalidSecretFormat(),
+
+    #[error("invalid transit input: {0}")]
+    InvalidTransitInput(String),
+
+    #[error("ttl must be between 1 and {0} seconds")]
+    InvalidTtl(i64),
+
     #[error("x-real-ip header malformed")]
     InvalidXRealIP(#[from] std::net::AddrParseError),
 
+    #[error("internal server error")]
+    IoError(#[from] std::io::Error),
+
+    #[error("this lease can't be renewed")]
+    LeaseNotRenewable(),
+
     #[error("not found")]
     NotFoundError(),
 
+    #[error("request body too large")]
+    PayloadTooLarge(),
+
+    #[error("operation not supported for this kind of secret")]
+    SecretKindMismatch(),
+
+    #[error("internal server error")]
+    TaskJoinError(#[from] tokio::task::JoinError),
+
+    #[error("template error: {0}")]
+    TemplateError(String),
+
+    #[error("too many requests")]
+    TooManyRequests(),
+
+    #[error("operation not supported for this type of key")]
+    TransitKeyTypeMismatch(),
+
     #[error("unauthorized")]
     TypedHeaderRejection(#[from] axum_extra::typed_header::TypedHeaderRejection),
 
     #[error("unauthorized")]
     Unauthorized(),
+
+    #[error("field name `{0}` can't be represented in this format")]
+    UnrenderableFieldName(String),
+
+    #[error("value of field `{0}` can't be represented in this format")]
+    UnrenderableFieldValue(String),
 }
 
 impl ResponseError {
@@ -56,9 +110,25 @@ impl ResponseError {
     /// matched here, the default is [StatusCode::INTERNAL_SERVER_ERROR].
     fn status_code(&self) -> StatusCode {
         match self {
-            Self::Unauthorized() | Self::TypedHeaderRejection(_) => StatusCode::UNAUTHORIZED,
-            Self::EmptyXRealIP(_) | Self::InvalidXRealIP(_) => StatusCode::BAD_REQUEST,
+            Self::ApprovalRequired() => StatusCode::FORBIDDEN,
+            Self::EmptyXRealIP(_)
+            | Self::InvalidCertificateRequest(_)
+            | Self::InvalidGeneratorSpec(_)
+            | Self::InvalidJustification()
+            | Self::InvalidSecretFormat()
+            | Self::InvalidTransitInput(_)
+            | Self::InvalidTtl(_)
+            | Self::InvalidXRealIP(_) => StatusCode::BAD_REQUEST,
+            Self::LeaseNotRenewable()
+            | Self::SecretKindMismatch()
+            | Self::TransitKeyTypeMismatch() => StatusCode::CONFLICT,
             Self::NotFoundError() => StatusCode::NOT_FOUND,
+            Self::PayloadTooLarge() => StatusCode::PAYLOAD_TOO_LARGE,
+            Self::TemplateError(_)
+            | Self::UnrenderableFieldName(_)
+            | Self::UnrenderableFieldValue(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            Self::TooManyRequests() => StatusCode::TOO_MANY_REQUESTS,
+            Self::TypedHeaderRejection(_) | Self::Unauthorized() => StatusCode::UNAUTHORIZED,
             _ => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
@@ -3,7 +3,7 @@ mod entities;
 mod errors;
 mod routers;
 
-use std::{net::SocketAddr, sync::Arc};
+use std::{net::SocketAddr, sync::Arc, time::Duration};
 
 use anyhow::Context;
 use clap::Parser;
@@ -13,12 +13,18 @@ use sqlx::{
 };
 use tokio::net::TcpListener;
 use tracing::info;
+use tracing_subscriber::fmt::writer::BoxMakeWriter;
 
 use crate::{
     components::{
         app_state::AppState,
-        settings::{LogFormat, Settings},
+        crl_cache::CrlCache,
+        lease_sweeper, nonce_sweeper,
+        rate_limiter::RateLimiter,
+        settings::{Command, LogFormat, Settings},
+        token_sweeper,
     },
+    entities::StaleTokenReport,
     routers::build_main_router,
 };
 
@@ -77,8 +83,14 @@ fn main() -> anyhow::Result<()> {
 async fn run(settings: Settings) -> anyhow::Result<()> {
     let settings_clone = settings.clone();
 
+    // Commands print their results to stdout, so logs have to go elsewhere.
+    let log_writer = match settings_clone.command {
+        Some(_) => BoxMakeWriter::new(std::io::stderr),
+        None => BoxMakeWriter::new(std::io::stdout),
+    };
     let subscriber = tracing_subscriber::fmt()
         .with_max_level(settings_clone.log_level.tracing_level())
+        .with_writer(log_writer)
         .with_target(false);
     match settings_clone.log_format {
         LogFormat::Text => subscriber.with_ansi(false).init(),
@@ -89,8 +101,29 @@ async fn run(settings: Settings) -> anyhow::Result<()> {
     let database = get_db_pool(settings_clone.database_url).await?;
     sqlx::migrate!().run(&database).await?;
 
+    if let Some(Command::StaleTokens {
+        idle_days,
+        expiring_within_days,
+    }) = settings_clone.command
+    {
+        let report = StaleTokenReport::build(&database, idle_days, expiring_within_days).await?;
+        println!("{}", serde_json::to_string_pretty(&report)?);
+        return Ok(());
+    }
+
+    tokio::spawn(lease_sweeper::run(
+        database.clone(),
+        Duration::from_secs(settings_clone.lease_sweep_interval),
+    ));
+    tokio::spawn(nonce_sweeper::run(database.clone()));
+    if let Some(idle_days) = settings_clone.idle_token_suspend_days {
+        tokio::spawn(token_sweeper::run(database.clone(), idle_days));
+    }
+
     let router = build_main_router(AppState {
         database,
+        rate_limiter: Arc::new(RateLimiter::new(&settings)),
+        crl_cache: Arc::new(CrlCache::default()),
         settings: Arc::new(settings),
     });
 
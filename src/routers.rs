@@ -1,9 +1,17 @@
+mod access_requests;
 mod app_meta;
+mod database;
+mod leases;
+mod login;
+mod pki;
 mod secrets;
+mod ssh;
+mod tokens;
+mod transit;
 
 use axum::{Router, middleware};
 
-use crate::{AppState, errors::ResponseError};
+use crate::{AppState, entities::digest_signed_body, errors::ResponseError};
 
 /// Builds the main router.
 /// This should collect all the routes from all over the app, and return a
@@ -13,8 +21,17 @@ pub fn build_main_router(state: AppState) -> Router {
         middleware::from_fn_with_state(state.clone(), ResponseError::handle_error_middleware);
 
     Router::new()
+        .merge(access_requests::build())
         .merge(app_meta::build())
+        .merge(database::build())
+        .merge(leases::build())
+        .merge(login::build())
+        .merge(pki::build())
         .merge(secrets::build())
+        .merge(ssh::build())
+        .merge(tokens::build())
+        .merge(transit::build())
+        .layer(middleware::from_fn(digest_signed_body))
         .layer(error_handling_layer)
         .fallback(fallback_handler)
         .with_state(state)
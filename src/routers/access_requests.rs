@@ -0,0 +1,94 @@
+use axum::{
+    Json, Router,
+    extract::{Path, State},
+    response::IntoResponse,
+    routing::{get, post},
+};
+use serde_json::json;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::{
+    components::app_state::AppState,
+    entities::{
+        AccessRequest, AuditLogAction, AuditLogEntry, ExtractClientAddr, ExtractValidToken,
+    },
+    errors::ResponseError,
+};
+
+pub fn build() -> Router<AppState> {
+    Router::new()
+        .route(
+            "/access-request/{uuid}/approve",
+            post(post_access_request_approve),
+        )
+        .route("/access-requests", get(list_access_requests))
+}
+
+/// Endpoint that lists all access requests that are waiting for an approval,
+/// oldest first. This requires a token with approver rights.
+#[axum::debug_handler]
+pub async fn list_access_requests(
+    State(state): State<AppState>,
+    ExtractValidToken(token): ExtractValidToken,
+) -> Result<Json<Vec<AccessRequest>>, ResponseError> {
+    if !token.is_approver() {
+        warn!("token=`{}` not allowed to list access requests", token.uuid);
+        return Err(ResponseError::Unauthorized());
+    }
+
+    Ok(Json(AccessRequest::list_pending(&state.database).await?))
+}
+
+/// Endpoint that approves a pending access request, which allows the requesting
+/// token to read the secret once within the approval window. This requires a
+/// token with approver rights, which can't be the requesting token, or one of
+/// its ancestors or descendants.
+#[axum::debug_handler]
+pub async fn post_access_request_approve(
+    State(state): State<AppState>,
+    Path(uuid): Path<Uuid>,
+    ExtractClientAddr(client_addr): ExtractClientAddr,
+    ExtractValidToken(token): ExtractValidToken,
+) -> Result<impl IntoResponse, ResponseError> {
+    if !token.is_approver() {
+        warn!(
+            "token=`{}` not allowed to approve access requests",
+            token.uuid
+        );
+        return Err(ResponseError::Unauthorized());
+    }
+
+    let mut tx = state.database.begin().await?;
+    let Some(mut request) = AccessRequest::try_find_pending_for_update(&mut tx, uuid).await? else {
+        return Err(ResponseError::NotFoundError());
+    };
+
+    if request.is_related_to(&mut *tx, token.uuid).await? {
+        warn!(
+            "token=`{}` not allowed to approve its own access request=`{}`",
+            token.uuid, request.uuid
+        );
+        return Err(ResponseError::Unauthorized());
+    }
+
+    request
+        .approve(&mut tx, token.uuid, state.settings.approval_window)
+        .await?;
+    let _ = AuditLogEntry::log_action_with_details(
+        &mut *tx,
+        client_addr.ip,
+        AuditLogAction::AccessApprove,
+        token.uuid,
+        request.secret,
+        json!({ "access_request": request.uuid, "requested_by": request.token }),
+    )
+    .await?;
+    tx.commit().await?;
+
+    info!(
+        "token=`{}` approved access request=`{}`",
+        token.uuid, request.uuid
+    );
+    Ok(Json(request))
+}
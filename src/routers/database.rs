@@ -0,0 +1,124 @@
+use axum::{
+    Json, Router,
+    extract::{Path, State},
+    response::IntoResponse,
+    routing::post,
+};
+use serde::Deserialize;
+use serde_json::json;
+use tracing::{error, warn};
+
+use crate::{
+    components::app_state::AppState,
+    entities::{
+        AuditLogAction, AuditLogEntry, DatabaseConnection, DatabaseLease, ExtractClientAddr,
+        ExtractValidToken, Lease, LeaseKind, Secret,
+    },
+    errors::ResponseError,
+};
+
+pub fn build() -> Router<AppState> {
+    Router::new().route(
+        "/database/{name}/credentials",
+        post(post_database_credentials),
+    )
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct CredentialsRequest {
+    ttl: Option<i64>,
+}
+
+/// Endpoint that creates a new role with a random password in the target
+/// database. The TTL (in seconds) defaults to the connection's default TTL, and
+/// can't exceed the maximum allowed by the token's roles, which is also the
+/// limit for renewals. The role gets dropped once the lease expires, or when it
+/// gets revoked, or right away if the lease can't be stored.
+#[axum::debug_handler]
+pub async fn post_database_credentials(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    ExtractClientAddr(client_addr): ExtractClientAddr,
+    ExtractValidToken(token): ExtractValidToken,
+    request: Option<Json<CredentialsRequest>>,
+) -> Result<impl IntoResponse, ResponseError> {
+    let request = request.map(|Json(request)| request).unwrap_or_default();
+
+    let Some(connection) = DatabaseConnection::try_find_by_name(&state.database, &name).await?
+    else {
+        return Err(ResponseError::NotFoundError());
+    };
+
+    let max_ttl = connection
+        .max_ttl_for(&state.database, token.uuid, token.superuser)
+        .await?;
+    let Some(max_ttl) = max_ttl else {
+        warn!(
+            "token=`{}` not allowed to request credentials for database=`{}`",
+            token.uuid, name
+        );
+        return Err(ResponseError::Unauthorized());
+    };
+
+    let ttl = request
+        .ttl
+        .unwrap_or(connection.default_ttl_seconds.min(max_ttl));
+    if !(1..=max_ttl).contains(&ttl) {
+        return Err(ResponseError::InvalidTtl(max_ttl));
+    }
+
+    let secret = Secret::find(&state.database, connection.secret).await?;
+    let credentials = connection.create_credentials(&secret, ttl).await?;
+
+    // The role already exists at this point. If it can't be tracked by a
+    // lease, nothing would ever drop it, so it's dropped right away instead.
+    let recorded = async {
+        let mut tx = state.database.begin().await?;
+        let lease = Lease::create(
+            &mut *tx,
+            LeaseKind::DatabaseCredentials,
+            &format!("database/{}/{}", name, credentials.username),
+            connection.secret,
+            token.uuid,
+            ttl,
+            max_ttl,
+        )
+        .await?;
+        DatabaseLease::create(&mut *tx, lease.uuid, connection.uuid, &credentials.username).await?;
+        let _ = AuditLogEntry::log_action_with_details(
+            &mut *tx,
+            client_addr.ip,
+            AuditLogAction::DatabaseCredentialsCreate,
+            token.uuid,
+            connection.secret,
+            json!({ "lease": lease.uuid, "username": credentials.username }),
+        )
+        .await?;
+        tx.commit().await?;
+        Ok::<_, ResponseError>(lease)
+    }
+    .await;
+
+    let lease = match recorded {
+        Ok(lease) => lease,
+        Err(e) => {
+            if let Err(revoke_error) = connection
+                .revoke_credentials(&secret, &credentials.username)
+                .await
+            {
+                error!(
+                    "could not drop role=`{}` on database=`{}` without a lease: {:?}",
+                    credentials.username, name, revoke_error
+                );
+            }
+            return Err(e);
+        }
+    };
+
+    Ok(Json(json!({
+        "lease_id": lease.uuid,
+        "username": credentials.username,
+        "password": credentials.password,
+        "expires_at": credentials.expires_at,
+    })))
+}
@@ -0,0 +1,167 @@
+use axum::{
+    Json, Router,
+    extract::{Path, State},
+    response::IntoResponse,
+    routing::post,
+};
+use serde::Deserialize;
+use serde_json::json;
+use sqlx::Acquire;
+use tracing::{error, warn};
+use uuid::Uuid;
+
+use crate::{
+    components::app_state::AppState,
+    entities::{AuditLogAction, AuditLogEntry, ExtractClientAddr, ExtractValidToken, Lease},
+    errors::ResponseError,
+};
+
+pub fn build() -> Router<AppState> {
+    Router::new()
+        .route("/lease/{uuid}/renew", post(post_lease_renew))
+        .route("/lease/{uuid}/revoke", post(post_lease_revoke))
+        .route("/leases/revoke-prefix", post(post_leases_revoke_prefix))
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct RenewRequest {
+    ttl: Option<i64>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RevokePrefixRequest {
+    prefix: String,
+}
+
+/// Endpoint that extends a lease by `ttl` seconds from now. Leases can't be
+/// renewed past the maximum TTL they were created with, which is also the
+/// default. Only the token that created a lease, or a superuser token, can
+/// renew it.
+#[axum::debug_handler]
+pub async fn post_lease_renew(
+    State(state): State<AppState>,
+    Path(uuid): Path<Uuid>,
+    ExtractClientAddr(client_addr): ExtractClientAddr,
+    ExtractValidToken(token): ExtractValidToken,
+    request: Option<Json<RenewRequest>>,
+) -> Result<impl IntoResponse, ResponseError> {
+    let request = request.map(|Json(request)| request).unwrap_or_default();
+
+    let mut tx = state.database.begin().await?;
+    let Some(mut lease) = Lease::try_find_active_for_update(&mut tx, uuid).await? else {
+        return Err(ResponseError::NotFoundError());
+    };
+
+    if !lease.is_owned_by(token.uuid, token.superuser) {
+        warn!(
+            "token=`{}` not allowed to renew lease=`{}`",
+            token.uuid, lease.uuid
+        );
+        return Err(ResponseError::Unauthorized());
+    }
+
+    lease.renew(&mut tx, request.ttl).await?;
+    let _ = AuditLogEntry::log_action_with_details(
+        &mut *tx,
+        client_addr.ip,
+        AuditLogAction::LeaseRenew,
+        token.uuid,
+        lease.secret,
+        json!({ "lease": lease.uuid, "path": lease.path, "expires_at": lease.expires_at }),
+    )
+    .await?;
+    tx.commit().await?;
+
+    Ok(Json(json!({
+        "lease_id": lease.uuid,
+        "path": lease.path,
+        "expires_at": lease.expires_at,
+        "max_expires_at": lease.max_expires_at,
+    })))
+}
+
+/// Endpoint that revokes a lease before it expires. Leases can only be revoked
+/// by the token that created them, or by a superuser token.
+#[axum::debug_handler]
+pub async fn post_lease_revoke(
+    State(state): State<AppState>,
+    Path(uuid): Path<Uuid>,
+    ExtractClientAddr(client_addr): ExtractClientAddr,
+    ExtractValidToken(token): ExtractValidToken,
+) -> Result<impl IntoResponse, ResponseError> {
+    let mut tx = state.database.begin().await?;
+    let Some(lease) = Lease::try_find_active_for_update(&mut tx, uuid).await? else {
+        return Err(ResponseError::NotFoundError());
+    };
+
+    if !lease.is_owned_by(token.uuid, token.superuser) {
+        warn!(
+            "token=`{}` not allowed to revoke lease=`{}`",
+            token.uuid, lease.uuid
+        );
+        return Err(ResponseError::Unauthorized());
+    }
+
+    lease.revoke(&mut tx).await?;
+    let _ = AuditLogEntry::log_action_with_details(
+        &mut *tx,
+        client_addr.ip,
+        lease.kind.revoke_action(),
+        token.uuid,
+        lease.secret,
+        json!({ "lease": lease.uuid, "path": lease.path }),
+    )
+    .await?;
+    tx.commit().await?;
+
+    Ok(Json(json!({ "lease_id": lease.uuid, "revoked": true })))
+}
+
+/// Endpoint that revokes all active leases whose path starts with a prefix,
+/// like `database/app/` to drop all roles created in the `app` database. This
+/// is meant for incident response, and requires a superuser token. Leases that
+/// fail to revoke are rolled back on their own, and returned in `failed`, so
+/// the request can be retried.
+#[axum::debug_handler]
+pub async fn post_leases_revoke_prefix(
+    State(state): State<AppState>,
+    ExtractClientAddr(client_addr): ExtractClientAddr,
+    ExtractValidToken(token): ExtractValidToken,
+    Json(request): Json<RevokePrefixRequest>,
+) -> Result<impl IntoResponse, ResponseError> {
+    if !token.superuser {
+        warn!(
+            "token=`{}` not allowed to revoke leases by prefix",
+            token.uuid
+        );
+        return Err(ResponseError::Unauthorized());
+    }
+
+    let mut tx = state.database.begin().await?;
+    let mut revoked = vec![];
+    let mut failed = vec![];
+    for lease in Lease::find_active_by_prefix_for_update(&mut tx, &request.prefix).await? {
+        let mut savepoint = tx.begin().await?;
+        if let Err(e) = lease.revoke(&mut savepoint).await {
+            error!("could not revoke lease=`{}`: {:?}", lease.uuid, e);
+            savepoint.rollback().await?;
+            failed.push(lease.uuid);
+            continue;
+        }
+        savepoint.commit().await?;
+
+        let _ = AuditLogEntry::log_action_with_details(
+            &mut *tx,
+            client_addr.ip,
+            lease.kind.revoke_action(),
+            token.uuid,
+            lease.secret,
+            json!({ "lease": lease.uuid, "path": lease.path, "prefix": request.prefix }),
+        )
+        .await?;
+        revoked.push(lease.uuid);
+    }
+    tx.commit().await?;
+
+    Ok(Json(json!({ "revoked": revoked, "failed": failed })))
+}
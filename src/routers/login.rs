@@ -0,0 +1,107 @@
+use axum::{Json, Router, extract::State, response::IntoResponse, routing::post};
+use serde::Deserialize;
+use serde_json::json;
+use tracing::{info, warn};
+
+use crate::{
+    components::app_state::AppState,
+    entities::{ExtractClientAddr, JwtIssuer, Token, unverified_issuer},
+    errors::ResponseError,
+};
+
+pub fn build() -> Router<AppState> {
+    Router::new().route("/login/jwt", post(post_login_jwt))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct JwtLoginRequest {
+    jwt: String,
+}
+
+/// Endpoint that exchanges a JWT from a trusted issuer for a short-lived token.
+/// The JWT has to be signed by one of the issuer's keys, and match at least one
+/// of the issuer's bindings. The token gets the roles of all matching bindings,
+/// and expires after the issuer's token TTL. All rejections result in a plain
+/// 401, the reason is only logged. Logins are rate limited by client address,
+/// just like requests with tokens.
+#[axum::debug_handler]
+pub async fn post_login_jwt(
+    State(state): State<AppState>,
+    ExtractClientAddr(client_addr): ExtractClientAddr,
+    Json(request): Json<JwtLoginRequest>,
+) -> Result<impl IntoResponse, ResponseError> {
+    state.rate_limiter.check_ip(client_addr.ip)?;
+
+    let Some(issuer_claim) = unverified_issuer(&request.jwt) else {
+        warn!("jwt login without a readable issuer");
+        return Err(ResponseError::Unauthorized());
+    };
+    let Some(issuer) = JwtIssuer::try_find_by_issuer(&state.database, &issuer_claim).await? else {
+        warn!("jwt login from unknown issuer=`{}`", issuer_claim);
+        return Err(ResponseError::Unauthorized());
+    };
+
+    let bindings = issuer.bindings(&state.database).await?;
+    let audiences: Vec<&str> = bindings
+        .iter()
+        .map(|binding| binding.audience.as_str())
+        .collect();
+    if audiences.is_empty() {
+        warn!("jwt login for issuer=`{}` without bindings", issuer.name);
+        return Err(ResponseError::Unauthorized());
+    }
+
+    let key_set = issuer.key_set().await?;
+    let claims = match issuer.verify(&request.jwt, &key_set, &audiences) {
+        Ok(claims) => claims,
+        Err(e) => {
+            warn!("rejected jwt from issuer=`{}`: {}", issuer.name, e);
+            return Err(ResponseError::Unauthorized());
+        }
+    };
+    let subject = claims
+        .get("sub")
+        .and_then(|sub| sub.as_str())
+        .unwrap_or_default();
+
+    let mut roles: Vec<_> = bindings
+        .iter()
+        .filter(|binding| binding.matches(&claims))
+        .map(|binding| binding.role)
+        .collect();
+    roles.sort();
+    roles.dedup();
+    if roles.is_empty() {
+        warn!(
+            "jwt for subject=`{}` from issuer=`{}` matches no binding",
+            subject, issuer.name
+        );
+        return Err(ResponseError::Unauthorized());
+    }
+
+    let mut tx = state.database.begin().await?;
+    let (token, token_value) = Token::create_for_jwt(
+        &mut tx,
+        issuer.uuid,
+        issuer.token_ttl_seconds,
+        &format!(
+            "jwt login for sub=`{}` from issuer `{}`",
+            subject, issuer.name
+        ),
+        &roles,
+    )
+    .await?;
+    let role_names = token.role_names(&mut *tx).await?;
+    tx.commit().await?;
+
+    info!(
+        "jwt login for subject=`{}` from issuer=`{}` created token=`{}`",
+        subject, issuer.name, token.uuid
+    );
+    Ok(Json(json!({
+        "uuid": token.uuid,
+        "token": token_value,
+        "expires_at": token.expires_at,
+        "roles": role_names,
+    })))
+}
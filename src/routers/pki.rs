@@ -0,0 +1,196 @@
+use axum::{
+    Json, Router,
+    extract::{Path, State},
+    http::header,
+    response::IntoResponse,
+    routing::{get, post},
+};
+use serde::Deserialize;
+use serde_json::json;
+use tracing::warn;
+
+use crate::{
+    components::app_state::AppState,
+    entities::{
+        AuditLogAction, AuditLogEntry, CertificateAuthority, CertificateSigner, ExtractClientAddr,
+        ExtractValidToken, Lease, LeaseKind, Secret,
+    },
+    errors::ResponseError,
+};
+
+pub fn build() -> Router<AppState> {
+    Router::new()
+        .route("/pki/{name}/ca", get(get_pki_ca))
+        .route("/pki/{name}/crl", get(get_pki_crl))
+        .route("/pki/{name}/issue", post(post_pki_issue))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct IssueRequest {
+    common_name: String,
+    #[serde(default)]
+    alt_names: Vec<String>,
+    ttl: Option<i64>,
+}
+
+/// Endpoint that issues a new leaf certificate. All requested names have to be
+/// allowed by one of the certificate policies of the token's roles, and the
+/// TTL (in seconds) can't exceed that policy's maximum. Without a TTL, the
+/// maximum is used. The certificate's private key is generated on the server
+/// and returned with the certificate, but it's not stored anywhere. Revoking
+/// the certificate's lease adds it to the CA's revocation list.
+#[axum::debug_handler]
+pub async fn post_pki_issue(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    ExtractClientAddr(client_addr): ExtractClientAddr,
+    ExtractValidToken(token): ExtractValidToken,
+    Json(request): Json<IssueRequest>,
+) -> Result<impl IntoResponse, ResponseError> {
+    let mut names = vec![request.common_name];
+    for alt_name in request.alt_names {
+        if !names.contains(&alt_name) {
+            names.push(alt_name);
+        }
+    }
+    if names.iter().any(|name| name.is_empty()) {
+        return Err(ResponseError::InvalidCertificateRequest(
+            "names can't be empty".to_string(),
+        ));
+    }
+
+    let Some(authority) = CertificateAuthority::try_find_by_name(&state.database, &name).await?
+    else {
+        return Err(ResponseError::NotFoundError());
+    };
+
+    let max_ttl = authority
+        .max_ttl_for(&state.database, token.uuid, token.superuser, &names)
+        .await?;
+    let Some(max_ttl) = max_ttl else {
+        warn!(
+            "token=`{}` not allowed to issue certificate for names=`{:?}` from ca=`{}`",
+            token.uuid, names, name
+        );
+        return Err(ResponseError::Unauthorized());
+    };
+
+    let ttl = request.ttl.unwrap_or(max_ttl);
+    if !(1..=max_ttl).contains(&ttl) {
+        return Err(ResponseError::InvalidTtl(max_ttl));
+    }
+
+    let secret = Secret::find(&state.database, authority.secret).await?;
+    let signer = CertificateSigner::load(&secret)?;
+    let issued = signer.issue(&names, ttl)?;
+
+    let mut tx = state.database.begin().await?;
+    let lease = Lease::create(
+        &mut *tx,
+        LeaseKind::Certificate,
+        &format!("pki/{}/{}", name, issued.serial_number),
+        authority.secret,
+        token.uuid,
+        ttl,
+        ttl,
+    )
+    .await?;
+    authority
+        .record_issued(&mut *tx, lease.uuid, token.uuid, &names, &issued)
+        .await?;
+    let _ = AuditLogEntry::log_action_with_details(
+        &mut *tx,
+        client_addr.ip,
+        AuditLogAction::CertificateIssue,
+        token.uuid,
+        authority.secret,
+        json!({
+            "serial_number": issued.serial_number,
+            "names": names,
+            "ttl": ttl,
+        }),
+    )
+    .await?;
+    tx.commit().await?;
+
+    Ok(Json(json!({
+        "serial_number": issued.serial_number,
+        "certificate": issued.certificate,
+        "private_key": issued.private_key,
+        "ca_certificate": signer.certificate,
+        "lease_id": lease.uuid,
+        "expires_at": issued.not_after,
+    })))
+}
+
+/// Endpoint that returns a CA's certificate, PEM-encoded. Just like the CRL,
+/// this doesn't require a token, so it can be used to set up trust stores.
+/// Requests are rate limited per client address.
+#[axum::debug_handler]
+pub async fn get_pki_ca(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    ExtractClientAddr(client_addr): ExtractClientAddr,
+) -> Result<impl IntoResponse, ResponseError> {
+    state.rate_limiter.check_ip(client_addr.ip)?;
+    let signer = load_signer(&state, &name).await?.1;
+    Ok((
+        [(header::CONTENT_TYPE, "application/x-pem-file")],
+        signer.certificate,
+    ))
+}
+
+/// Endpoint that returns a DER-encoded CRL for a CA. This doesn't require a
+/// token, as TLS clients checking the CRL won't have one. Requests are rate
+/// limited per client address, and the CRL is only signed again once a
+/// certificate was revoked or expired, or the cached CRL is due for a refresh.
+#[axum::debug_handler]
+pub async fn get_pki_crl(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    ExtractClientAddr(client_addr): ExtractClientAddr,
+) -> Result<impl IntoResponse, ResponseError> {
+    state.rate_limiter.check_ip(client_addr.ip)?;
+
+    let Some(authority) = CertificateAuthority::try_find_by_name(&state.database, &name).await?
+    else {
+        return Err(ResponseError::NotFoundError());
+    };
+
+    let revocations = authority.revocation_state(&state.database).await?;
+    let crl = match state.crl_cache.get(authority.uuid, &revocations) {
+        Some(crl) => crl,
+        None => {
+            let signer = load_authority_signer(&state, &authority).await?;
+            let crl = authority.revocation_list(&state.database, &signer).await?;
+            state
+                .crl_cache
+                .insert(authority.uuid, revocations, crl.clone());
+            crl
+        }
+    };
+
+    Ok(([(header::CONTENT_TYPE, "application/pkix-crl")], crl))
+}
+
+async fn load_signer(
+    state: &AppState,
+    name: &str,
+) -> Result<(CertificateAuthority, CertificateSigner), ResponseError> {
+    let Some(authority) = CertificateAuthority::try_find_by_name(&state.database, name).await?
+    else {
+        return Err(ResponseError::NotFoundError());
+    };
+
+    let signer = load_authority_signer(state, &authority).await?;
+    Ok((authority, signer))
+}
+
+/// Loads the CA material of a CA that has already been looked up.
+async fn load_authority_signer(
+    state: &AppState,
+    authority: &CertificateAuthority,
+) -> Result<CertificateSigner, ResponseError> {
+    let secret = Secret::find(&state.database, authority.secret).await?;
+    CertificateSigner::load(&secret)
+}
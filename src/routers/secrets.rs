@@ -1,54 +1,287 @@
+use std::collections::{BTreeMap, HashMap};
+
 use axum::{
-    Router,
+    Json, Router,
     body::{Body, Bytes},
     extract::{Path, State},
     http::StatusCode,
     response::{IntoResponse, Response},
     routing::{get, post},
 };
-use tracing::warn;
+use axum_extra::extract::Query;
+use serde::Deserialize;
+use serde_json::json;
+use sqlx::{PgConnection, PgExecutor};
+use tracing::{info, warn};
 use uuid::Uuid;
 
 use crate::{
     components::app_state::AppState,
-    entities::{AuditLogAction, AuditLogEntry, ExtractClientAddr, ExtractValidToken, Secret},
+    entities::{
+        AccessRequest, AuditLogAction, AuditLogEntry, BreakGlassReads, ClientAddr,
+        ExtractClientAddr, ExtractJustification, ExtractSecretFormat, ExtractValidToken,
+        GeneratorSpec, Secret, SecretBundle, SecretFormat, SecretKind, SecretReference,
+        SecretSummary, SecretTemplate, Token,
+    },
     errors::ResponseError,
 };
 
 pub fn build() -> Router<AppState> {
     Router::new()
+        .route("/secrets", get(list_secrets))
+        .route("/secrets/bundle", post(post_secrets_bundle))
         .route("/secret/{uuid}", get(get_secret))
         .route("/secret/{uuid}/contents", post(post_secret_contents))
+        .route(
+            "/secret/{uuid}/fields",
+            get(get_secret_fields).patch(patch_secret_fields),
+        )
+        .route("/secret/{uuid}/field/{name}", get(get_secret_field))
+        .route("/secret/{uuid}/generate", post(post_secret_generate))
+        .route("/secret/{uuid}/template", get(get_secret_template))
+        .route(
+            "/secret/by-path/{*path}",
+            get(get_secret_by_path).post(post_secret_contents_by_path),
+        )
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ListSecretsQuery {
+    #[serde(default, rename = "tag")]
+    tags: Vec<String>,
+}
+
+/// Endpoint that lists all secrets the token can read or write, optionally
+/// filtered by one or more `tag` query parameters. Only metadata is returned,
+/// never the contents.
+#[axum::debug_handler]
+pub async fn list_secrets(
+    State(state): State<AppState>,
+    Query(query): Query<ListSecretsQuery>,
+    ExtractValidToken(token): ExtractValidToken,
+) -> Result<Json<Vec<SecretSummary>>, ResponseError> {
+    Ok(Json(
+        SecretSummary::list_accessible(&state.database, token.uuid, token.superuser, &query.tags)
+            .await?,
+    ))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BundleRequest {
+    secrets: Vec<String>,
+    #[serde(default)]
+    allow_partial: bool,
+    format: Option<SecretFormat>,
+}
+
+/// Endpoint that returns multiple secrets, identified by either their UUID or
+/// their path, as a single tar archive. All permission checks and audit log
+/// entries happen in one transaction. If the token can't read any of the
+/// requested secrets, the whole request fails with a 401, unless
+/// `allow_partial` is set. In that case, the unreadable secrets are skipped
+/// and only noted in the archive's manifest. Secrets that require approval
+/// can't be bundled, and are treated the same way, but with a 403. Break-glass
+/// tokens that give a justification can bundle any secret.
+#[axum::debug_handler]
+pub async fn post_secrets_bundle(
+    State(state): State<AppState>,
+    ExtractClientAddr(client_addr): ExtractClientAddr,
+    ExtractValidToken(token): ExtractValidToken,
+    ExtractJustification(justification): ExtractJustification,
+    Json(request): Json<BundleRequest>,
+) -> Result<Response, ResponseError> {
+    let mut break_glass = BreakGlassReads::start(&token, justification.as_deref());
+    let mut tx = state.database.begin().await?;
+    let mut bundle = SecretBundle::new(request.format);
+
+    for requested in request.secrets {
+        let Some(uuid) =
+            resolve_readable(&mut tx, &requested, &token, break_glass.is_some()).await?
+        else {
+            warn!(
+                "token=`{}` not allowed to read bundled secret=`{}`",
+                token.uuid, requested
+            );
+            if !request.allow_partial {
+                return Err(ResponseError::Unauthorized());
+            }
+
+            bundle.skip_unauthorized(requested);
+            continue;
+        };
+
+        if bundle.try_add_alias(&requested, uuid) {
+            continue;
+        }
+
+        let mut secret = Secret::find(&mut *tx, uuid).await?;
+        if secret.requires_approval && break_glass.is_none() {
+            warn!(
+                "token=`{}` tried to bundle secret=`{}`, which requires approval",
+                token.uuid, uuid
+            );
+            if !request.allow_partial {
+                return Err(ResponseError::ApprovalRequired());
+            }
+
+            bundle.skip_unauthorized(requested);
+            continue;
+        }
+
+        log_secret_read(
+            &mut *tx,
+            &client_addr,
+            &token,
+            secret.uuid,
+            &mut break_glass,
+        )
+        .await?;
+        if secret.kind == SecretKind::Template {
+            render_template(&mut tx, &mut secret, &client_addr, &token, &mut break_glass).await?;
+        }
+        bundle.add(requested, secret)?;
+    }
+
+    tx.commit().await?;
+    notify_break_glass(&state, &client_addr, &token, break_glass);
+    bundle.finish()
 }
 
 /// Endpoint that allows reading secrets. All requests require a valid token. In
 /// addition, all requests are gated behind the can_read token permissions. It
 /// always returns a 401 if the token is valid but can't read a secret, no
-/// matter if the secret actually exists or not.
+/// matter if the secret actually exists or not. Structured secrets are
+/// rendered in the format requested via the `format` query or Accept header,
+/// and template secrets are rendered before they're returned. Secrets that
+/// require approval respond with a 202 and a pending access request instead,
+/// until the request is approved. Templates that reference such a secret can't
+/// be rendered, except for break-glass reads.
 #[axum::debug_handler]
 pub async fn get_secret(
     State(state): State<AppState>,
     Path(uuid): Path<Uuid>,
     ExtractClientAddr(client_addr): ExtractClientAddr,
     ExtractValidToken(token): ExtractValidToken,
+    ExtractSecretFormat(format): ExtractSecretFormat,
+    ExtractJustification(justification): ExtractJustification,
 ) -> Result<Response, ResponseError> {
-    if !token.can_read_secret(&state.database, uuid).await? {
-        warn!(
-            "token=`{}` not allowed to read secret=`{}`",
-            token.uuid, uuid
-        );
-        return Err(ResponseError::Unauthorized());
+    let mut break_glass = BreakGlassReads::start(&token, justification.as_deref());
+    let read =
+        read_secret_or_request_access(&state, uuid, None, &client_addr, &token, &mut break_glass);
+    let mut secret = match read.await? {
+        Ok(secret) => secret,
+        Err(pending) => return Ok(pending),
+    };
+    if secret.kind == SecretKind::Template {
+        let mut tx = state.database.begin().await?;
+        render_template(&mut tx, &mut secret, &client_addr, &token, &mut break_glass).await?;
+        tx.commit().await?;
     }
+    notify_break_glass(&state, &client_addr, &token, break_glass);
 
-    let secret = Secret::find(&state.database, uuid).await?;
-    let _ = AuditLogEntry::log_action(
-        &state.database,
-        client_addr.ip,
-        AuditLogAction::SecretRead,
-        token.uuid,
-        secret.uuid,
-    )
-    .await?;
+    secret.into_formatted_response(format)
+}
+
+/// Same as [get_secret], but the secret is looked up by its path instead of its
+/// UUID. Unknown paths also result in a 401, so this can't be used to check
+/// which paths exist.
+#[axum::debug_handler]
+pub async fn get_secret_by_path(
+    state: State<AppState>,
+    Path(path): Path<String>,
+    client_addr: ExtractClientAddr,
+    token: ExtractValidToken,
+    format: ExtractSecretFormat,
+    justification: ExtractJustification,
+) -> Result<Response, ResponseError> {
+    let uuid = resolve_path(&state, &path, &token).await?;
+    get_secret(state, Path(uuid), client_addr, token, format, justification).await
+}
+
+/// Endpoint that returns all fields of a structured secret as a JSON object.
+/// The same permission and approval rules as in [get_secret] apply.
+#[axum::debug_handler]
+pub async fn get_secret_fields(
+    State(state): State<AppState>,
+    Path(uuid): Path<Uuid>,
+    ExtractClientAddr(client_addr): ExtractClientAddr,
+    ExtractValidToken(token): ExtractValidToken,
+    ExtractJustification(justification): ExtractJustification,
+) -> Result<Response, ResponseError> {
+    let mut break_glass = BreakGlassReads::start(&token, justification.as_deref());
+    let read = read_secret_or_request_access(
+        &state,
+        uuid,
+        Some(SecretKind::Structured),
+        &client_addr,
+        &token,
+        &mut break_glass,
+    );
+    let secret = match read.await? {
+        Ok(secret) => secret,
+        Err(pending) => return Ok(pending),
+    };
+    notify_break_glass(&state, &client_addr, &token, break_glass);
+
+    Ok(Json(secret.fields()).into_response())
+}
+
+/// Endpoint that returns a single field of a structured secret as plain text.
+/// The same permission and approval rules as in [get_secret] apply. Returns a
+/// 404 if the field does not exist.
+#[axum::debug_handler]
+pub async fn get_secret_field(
+    State(state): State<AppState>,
+    Path((uuid, name)): Path<(Uuid, String)>,
+    ExtractClientAddr(client_addr): ExtractClientAddr,
+    ExtractValidToken(token): ExtractValidToken,
+    ExtractJustification(justification): ExtractJustification,
+) -> Result<Response, ResponseError> {
+    let mut break_glass = BreakGlassReads::start(&token, justification.as_deref());
+    let read = read_secret_or_request_access(
+        &state,
+        uuid,
+        Some(SecretKind::Structured),
+        &client_addr,
+        &token,
+        &mut break_glass,
+    );
+    let secret = match read.await? {
+        Ok(secret) => secret,
+        Err(pending) => return Ok(pending),
+    };
+    notify_break_glass(&state, &client_addr, &token, break_glass);
+
+    match secret.fields().remove(&name) {
+        Some(value) => Ok(value.into_response()),
+        None => Err(ResponseError::NotFoundError()),
+    }
+}
+
+/// Endpoint that returns the unrendered source of a template secret. The same
+/// permission and approval rules as in [get_secret] apply.
+#[axum::debug_handler]
+pub async fn get_secret_template(
+    State(state): State<AppState>,
+    Path(uuid): Path<Uuid>,
+    ExtractClientAddr(client_addr): ExtractClientAddr,
+    ExtractValidToken(token): ExtractValidToken,
+    ExtractJustification(justification): ExtractJustification,
+) -> Result<Response, ResponseError> {
+    let mut break_glass = BreakGlassReads::start(&token, justification.as_deref());
+    let read = read_secret_or_request_access(
+        &state,
+        uuid,
+        Some(SecretKind::Template),
+        &client_addr,
+        &token,
+        &mut break_glass,
+    );
+    let secret = match read.await? {
+        Ok(secret) => secret,
+        Err(pending) => return Ok(pending),
+    };
+    notify_break_glass(&state, &client_addr, &token, break_glass);
 
     Ok(secret.into_response())
 }
@@ -65,15 +298,243 @@ pub async fn post_secret_contents(
     ExtractValidToken(token): ExtractValidToken,
     body: Bytes,
 ) -> Result<Response, ResponseError> {
+    let mut secret = write_secret(
+        &state,
+        uuid,
+        &[SecretKind::Blob, SecretKind::Template],
+        &client_addr,
+        &token,
+    )
+    .await?;
+
+    let _ = secret
+        .update_contents(&state.database, body.to_vec())
+        .await?;
+
+    Ok((StatusCode::NO_CONTENT, Body::empty()).into_response())
+}
+
+/// Same as [post_secret_contents], but the secret is looked up by its path
+/// instead of its UUID. Unknown paths also result in a 401.
+#[axum::debug_handler]
+pub async fn post_secret_contents_by_path(
+    state: State<AppState>,
+    Path(path): Path<String>,
+    client_addr: ExtractClientAddr,
+    token: ExtractValidToken,
+    body: Bytes,
+) -> Result<Response, ResponseError> {
+    let uuid = resolve_path(&state, &path, &token).await?;
+    post_secret_contents(state, Path(uuid), client_addr, token, body).await
+}
+
+/// Endpoint that updates individual fields of a structured secret. The body
+/// is a JSON object, where fields with a string value are set, and fields with
+/// a `null` value are removed. The same permission rules as in
+/// [post_secret_contents] apply.
+#[axum::debug_handler]
+pub async fn patch_secret_fields(
+    State(state): State<AppState>,
+    Path(uuid): Path<Uuid>,
+    ExtractClientAddr(client_addr): ExtractClientAddr,
+    ExtractValidToken(token): ExtractValidToken,
+    Json(patch): Json<BTreeMap<String, Option<String>>>,
+) -> Result<Response, ResponseError> {
+    let mut secret = write_secret(
+        &state,
+        uuid,
+        &[SecretKind::Structured],
+        &client_addr,
+        &token,
+    )
+    .await?;
+
+    secret.patch_fields(&state.database, patch).await?;
+
+    Ok((StatusCode::NO_CONTENT, Body::empty()).into_response())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct GenerateQuery {
+    #[serde(default)]
+    reveal: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct GenerateRequest {
+    #[serde(flatten)]
+    spec: GeneratorSpec,
+    field: Option<String>,
+}
+
+/// Endpoint that generates a new random value according to the given spec,
+/// and stores it in the secret. For structured secrets, `field` picks the
+/// field to store the value in. The same permission rules as in
+/// [post_secret_contents] apply. The generated value is only returned if the
+/// `reveal` query parameter is set, which also requires read permissions. The
+/// public key of generated keypairs is always returned.
+#[axum::debug_handler]
+pub async fn post_secret_generate(
+    State(state): State<AppState>,
+    Path(uuid): Path<Uuid>,
+    Query(query): Query<GenerateQuery>,
+    ExtractClientAddr(client_addr): ExtractClientAddr,
+    ExtractValidToken(token): ExtractValidToken,
+    Json(request): Json<GenerateRequest>,
+) -> Result<Response, ResponseError> {
+    request.spec.validate()?;
+    if query.reveal && !token.can_read_secret(&state.database, uuid).await? {
+        warn!(
+            "token=`{}` not allowed to reveal secret=`{}`",
+            token.uuid, uuid
+        );
+        return Err(deny_secret_access(&state, uuid, false, &client_addr, &token).await?);
+    }
+
+    // Values go into a field for structured secrets, and replace the contents
+    // otherwise, so the kind has to be checked before the write is audited.
+    let kind = match request.field {
+        Some(_) => SecretKind::Structured,
+        None => SecretKind::Blob,
+    };
+    let mut secret = write_secret(&state, uuid, &[kind], &client_addr, &token).await?;
+
+    let spec = request.spec;
+    let generated = tokio::task::spawn_blocking(move || spec.generate()).await??;
+    match request.field {
+        None => {
+            let _ = secret
+                .update_contents(&state.database, generated.value.clone().into_bytes())
+                .await?;
+        }
+        Some(field) => {
+            let patch = BTreeMap::from([(field, Some(generated.value.clone()))]);
+            secret.patch_fields(&state.database, patch).await?;
+        }
+    }
+
+    if query.reveal {
+        let _ = AuditLogEntry::log_action(
+            &state.database,
+            client_addr.ip,
+            AuditLogAction::SecretRead,
+            token.uuid,
+            secret.uuid,
+        )
+        .await?;
+    }
+
+    Ok(Json(json!({
+        "uuid": secret.uuid,
+        "public_key": generated.public_key,
+        "value": query.reveal.then_some(generated.value),
+    }))
+    .into_response())
+}
+
+/// Resolves a secret's path into its UUID, or rejects the request with a 401
+/// if there is no secret with that path.
+async fn resolve_path(
+    state: &AppState,
+    path: &str,
+    ExtractValidToken(token): &ExtractValidToken,
+) -> Result<Uuid, ResponseError> {
+    match Secret::try_find_uuid_by_path(&state.database, path).await? {
+        Some(uuid) => Ok(uuid),
+        None => {
+            warn!("token=`{}` requested unknown path=`{}`", token.uuid, path);
+            Err(ResponseError::Unauthorized())
+        }
+    }
+}
+
+/// Loads a secret for reading. This checks the token's permissions and, if
+/// `kind` is set, that the secret is of that kind. Only then it stores the read
+/// in the audit log. Secrets that require approval are rejected, they can only
+/// be read via [read_approved_secret]. Break-glass reads skip the permission
+/// and approval checks.
+async fn read_secret(
+    state: &AppState,
+    uuid: Uuid,
+    kind: Option<SecretKind>,
+    client_addr: &ClientAddr,
+    token: &Token,
+    break_glass: &mut Option<BreakGlassReads<'_>>,
+) -> Result<Secret, ResponseError> {
+    if break_glass.is_none() && !token.can_read_secret(&state.database, uuid).await? {
+        warn!(
+            "token=`{}` not allowed to read secret=`{}`",
+            token.uuid, uuid
+        );
+        return Err(deny_secret_access(state, uuid, false, client_addr, token).await?);
+    }
+
+    let secret = Secret::find(&state.database, uuid).await?;
+    if kind.is_some_and(|kind| kind != secret.kind) {
+        return Err(ResponseError::SecretKindMismatch());
+    }
+
+    if secret.requires_approval && break_glass.is_none() {
+        return Err(ResponseError::ApprovalRequired());
+    }
+
+    log_secret_read(
+        &state.database,
+        client_addr,
+        token,
+        secret.uuid,
+        break_glass,
+    )
+    .await?;
+    Ok(secret)
+}
+
+/// Like [read_secret], but secrets that require approval go through the
+/// approval flow: an approved access request gets used up, otherwise the
+/// result is the 202 response with the pending request, which the caller
+/// should return as-is.
+async fn read_secret_or_request_access(
+    state: &AppState,
+    uuid: Uuid,
+    kind: Option<SecretKind>,
+    client_addr: &ClientAddr,
+    token: &Token,
+    break_glass: &mut Option<BreakGlassReads<'_>>,
+) -> Result<Result<Secret, Response>, ResponseError> {
+    match read_secret(state, uuid, kind, client_addr, token, break_glass).await {
+        Err(ResponseError::ApprovalRequired()) => {
+            match read_approved_secret(state, uuid, client_addr, token).await? {
+                Some(secret) => Ok(Ok(secret)),
+                None => Ok(Err(request_access(state, uuid, client_addr, token).await?)),
+            }
+        }
+        result => Ok(Ok(result?)),
+    }
+}
+
+/// Loads a secret for writing. This checks the token's permissions and that
+/// the secret is of one of the given kinds. Only then it stores the write in
+/// the audit log, so the caller only has to do the actual update.
+async fn write_secret(
+    state: &AppState,
+    uuid: Uuid,
+    kinds: &[SecretKind],
+    client_addr: &ClientAddr,
+    token: &Token,
+) -> Result<Secret, ResponseError> {
     if !token.can_write_secret(&state.database, uuid).await? {
         warn!(
             "token=`{}` not allowed to write secret=`{}`",
             token.uuid, uuid
         );
-        return Err(ResponseError::Unauthorized());
+        return Err(deny_secret_access(state, uuid, true, client_addr, token).await?);
+    }
+
+    let secret = Secret::find(&state.database, uuid).await?;
+    if !kinds.contains(&secret.kind) {
+        return Err(ResponseError::SecretKindMismatch());
     }
 
-    let mut secret = Secret::find(&state.database, uuid).await?;
     let _ = AuditLogEntry::log_action(
         &state.database,
         client_addr.ip,
@@ -83,9 +544,245 @@ pub async fn post_secret_contents(
     )
     .await?;
 
-    let _ = secret
-        .update_contents(&state.database, body.to_vec())
+    Ok(secret)
+}
+
+/// Loads a secret that requires approval, using up the token's approved access
+/// request for it. The read is stored in the audit log, together with the
+/// request. Returns None() if the token has no approved request that can still
+/// be used. The caller has to check the token's permissions first.
+async fn read_approved_secret(
+    state: &AppState,
+    uuid: Uuid,
+    client_addr: &ClientAddr,
+    token: &Token,
+) -> Result<Option<Secret>, ResponseError> {
+    let mut tx = state.database.begin().await?;
+    let Some(request) =
+        AccessRequest::try_find_readable_for_update(&mut tx, token.uuid, uuid).await?
+    else {
+        return Ok(None);
+    };
+
+    request.mark_read(&mut tx).await?;
+    let secret = Secret::find(&mut *tx, uuid).await?;
+    let _ = AuditLogEntry::log_action_with_details(
+        &mut *tx,
+        client_addr.ip,
+        AuditLogAction::SecretRead,
+        token.uuid,
+        secret.uuid,
+        json!({ "access_request": request.uuid, "approved_by": request.approved_by }),
+    )
+    .await?;
+    tx.commit().await?;
+
+    Ok(Some(secret))
+}
+
+/// Responds with a 202 and the token's pending access request for a secret.
+/// If there is none yet, a new one is created and stored in the audit log.
+async fn request_access(
+    state: &AppState,
+    uuid: Uuid,
+    client_addr: &ClientAddr,
+    token: &Token,
+) -> Result<Response, ResponseError> {
+    let mut tx = state.database.begin().await?;
+    let request = match AccessRequest::try_find_pending(&mut *tx, token.uuid, uuid).await? {
+        Some(request) => request,
+        None => {
+            let request = AccessRequest::create(&mut *tx, uuid, token.uuid).await?;
+            let _ = AuditLogEntry::log_action_with_details(
+                &mut *tx,
+                client_addr.ip,
+                AuditLogAction::AccessRequest,
+                token.uuid,
+                uuid,
+                json!({ "access_request": request.uuid }),
+            )
+            .await?;
+            info!(
+                "token=`{}` requested access to secret=`{}`",
+                token.uuid, uuid
+            );
+            request
+        }
+    };
+    tx.commit().await?;
+
+    Ok((
+        StatusCode::ACCEPTED,
+        Json(json!({
+            "request_id": request.uuid,
+            "status": "pending",
+            "expires_at": request.expires_at,
+        })),
+    )
+        .into_response())
+}
+
+/// Stores a read in the audit log. Break-glass reads are stored with a high
+/// severity and the justification instead.
+async fn log_secret_read<'e>(
+    db: impl PgExecutor<'e>,
+    client_addr: &ClientAddr,
+    token: &Token,
+    secret: Uuid,
+    break_glass: &mut Option<BreakGlassReads<'_>>,
+) -> Result<(), sqlx::Error> {
+    match break_glass {
+        Some(break_glass) => {
+            break_glass
+                .log(db, client_addr.ip, token.uuid, secret)
+                .await
+        }
+        None => AuditLogEntry::log_action(
+            db,
+            client_addr.ip,
+            AuditLogAction::SecretRead,
+            token.uuid,
+            secret,
+        )
+        .await
+        .map(|_| ()),
+    }
+}
+
+/// Notifies the break-glass webhooks about every secret that was read with
+/// break-glass access. This should only be called once the reads are
+/// committed.
+fn notify_break_glass(
+    state: &AppState,
+    client_addr: &ClientAddr,
+    token: &Token,
+    break_glass: Option<BreakGlassReads<'_>>,
+) {
+    if let Some(break_glass) = break_glass {
+        break_glass.notify(
+            &state.settings.break_glass_webhooks,
+            client_addr.ip,
+            token.uuid,
+        );
+    }
+}
+
+/// Stores a denied read or write in the audit log, together with the reason,
+/// so accesses outside of an access schedule can be told apart from accesses
+/// without any permission. Returns the error to respond with.
+async fn deny_secret_access(
+    state: &AppState,
+    uuid: Uuid,
+    write: bool,
+    client_addr: &ClientAddr,
+    token: &Token,
+) -> Result<ResponseError, ResponseError> {
+    let reason = token
+        .secret_denial_reason(&state.database, uuid, write)
         .await?;
+    let _ = AuditLogEntry::log_action_with_details(
+        &state.database,
+        client_addr.ip,
+        AuditLogAction::AccessDenied,
+        token.uuid,
+        uuid,
+        json!({ "reason": reason, "operation": if write { "write" } else { "read" } }),
+    )
+    .await?;
 
-    Ok((StatusCode::NO_CONTENT, Body::empty()).into_response())
+    Ok(ResponseError::Unauthorized())
+}
+
+/// Resolves an identifier, which can be either a UUID or a path, and checks if
+/// the token can read that secret. Returns None() if there is no secret with
+/// that path, or if the token isn't allowed to read it. For break-glass reads,
+/// the secret only has to exist.
+async fn resolve_readable(
+    conn: &mut PgConnection,
+    identifier: &str,
+    token: &Token,
+    break_glass: bool,
+) -> Result<Option<Uuid>, sqlx::Error> {
+    let uuid = match Uuid::parse_str(identifier) {
+        Ok(uuid) => uuid,
+        Err(_) => match Secret::try_find_uuid_by_path(&mut *conn, identifier).await? {
+            Some(uuid) => uuid,
+            None => return Ok(None),
+        },
+    };
+
+    let readable = if break_glass {
+        Secret::exists(&mut *conn, uuid).await?
+    } else {
+        token.can_read_secret(&mut *conn, uuid).await?
+    };
+    if readable { Ok(Some(uuid)) } else { Ok(None) }
+}
+
+/// Renders a template secret, replacing its contents with the rendered
+/// template. Every referenced secret is subject to the same permission checks
+/// as a direct read, and gets its own audit log entry. If the token can't read
+/// any of them, the whole template is rejected with a 401. Referenced secrets
+/// that require approval reject the template with a 403, as there is no access
+/// request flow for them. During break-glass reads, all referenced secrets are
+/// break-glass reads as well.
+async fn render_template(
+    conn: &mut PgConnection,
+    secret: &mut Secret,
+    client_addr: &ClientAddr,
+    token: &Token,
+    break_glass: &mut Option<BreakGlassReads<'_>>,
+) -> Result<(), ResponseError> {
+    let source = String::from_utf8(secret.contents.take().unwrap_or_default())
+        .map_err(|_| ResponseError::TemplateError("template is not valid UTF-8".to_string()))?;
+    let template = SecretTemplate::parse(&source)?;
+
+    let mut values = HashMap::new();
+    for reference in template.references() {
+        let Some(uuid) =
+            resolve_readable(&mut *conn, &reference.secret, token, break_glass.is_some()).await?
+        else {
+            warn!(
+                "token=`{}` not allowed to read secret=`{}` referenced in template=`{}`",
+                token.uuid, reference.secret, secret.uuid
+            );
+            return Err(ResponseError::Unauthorized());
+        };
+
+        let referenced = Secret::find(&mut *conn, uuid).await?;
+        if referenced.requires_approval && break_glass.is_none() {
+            warn!(
+                "secret=`{}` referenced in template=`{}` requires approval",
+                referenced.uuid, secret.uuid
+            );
+            return Err(ResponseError::ApprovalRequired());
+        }
+
+        log_secret_read(&mut *conn, client_addr, token, referenced.uuid, break_glass).await?;
+        values.insert(reference.clone(), reference_value(reference, referenced)?);
+    }
+
+    secret.contents = Some(template.render(&values).into_bytes());
+    Ok(())
+}
+
+/// Extracts the value a template reference points to. Blob secrets are
+/// inserted as a whole, structured secrets need a field. Templates can't be
+/// referenced, so there is no way to build loops.
+fn reference_value(reference: &SecretReference, secret: Secret) -> Result<String, ResponseError> {
+    let error = |message: &str| {
+        ResponseError::TemplateError(format!("secret `{}` {}", reference.secret, message))
+    };
+
+    match (secret.kind, &reference.field) {
+        (SecretKind::Blob, None) => String::from_utf8(secret.contents.unwrap_or_default())
+            .map_err(|_| error("is not valid UTF-8")),
+        (SecretKind::Blob, Some(_)) => Err(error("has no fields")),
+        (SecretKind::Structured, Some(field)) => secret
+            .fields()
+            .remove(field)
+            .ok_or_else(|| error(&format!("has no field `{}`", field))),
+        (SecretKind::Structured, None) => Err(error("is structured and needs a field")),
+        (SecretKind::Template, _) => Err(error("is a template itself")),
+    }
 }
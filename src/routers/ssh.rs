@@ -0,0 +1,151 @@
+use axum::{
+    Json, Router,
+    extract::{Path, State},
+    http::header,
+    response::IntoResponse,
+    routing::{get, post},
+};
+use serde::Deserialize;
+use serde_json::json;
+use ssh_key::PublicKey;
+use tracing::warn;
+
+use crate::{
+    components::app_state::AppState,
+    entities::{
+        AuditLogAction, AuditLogEntry, ExtractClientAddr, ExtractValidToken, Secret,
+        SshCertificateAuthority, SshCertificateType,
+    },
+    errors::ResponseError,
+};
+
+pub fn build() -> Router<AppState> {
+    Router::new()
+        .route("/ssh/{name}/public-key", get(get_ssh_public_key))
+        .route("/ssh/{name}/sign", post(post_ssh_sign))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SignRequest {
+    public_key: String,
+    #[serde(default)]
+    certificate_type: SshCertificateType,
+    principals: Vec<String>,
+    extensions: Option<Vec<String>>,
+    ttl: Option<i64>,
+}
+
+/// Endpoint that signs an SSH public key with an SSH CA. The principals, the
+/// extensions, and the TTL (in seconds) have to be allowed by one of the SSH
+/// policies of the token's roles. Without explicit extensions or TTL, the
+/// policy's allowed extensions and its maximum TTL are used. The certificate's
+/// key ID contains the token's UUID, so signed keys can be traced back in the
+/// server logs.
+#[axum::debug_handler]
+pub async fn post_ssh_sign(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    ExtractClientAddr(client_addr): ExtractClientAddr,
+    ExtractValidToken(token): ExtractValidToken,
+    Json(request): Json<SignRequest>,
+) -> Result<impl IntoResponse, ResponseError> {
+    let public_key = PublicKey::from_openssh(&request.public_key)
+        .map_err(|_| ResponseError::InvalidCertificateRequest("invalid public key".to_string()))?;
+    if request.principals.is_empty() || request.principals.iter().any(String::is_empty) {
+        return Err(ResponseError::InvalidCertificateRequest(
+            "at least one principal is required, and principals can't be empty".to_string(),
+        ));
+    }
+
+    let Some(authority) = SshCertificateAuthority::try_find_by_name(&state.database, &name).await?
+    else {
+        return Err(ResponseError::NotFoundError());
+    };
+
+    let grant = authority
+        .grant_for(
+            &state.database,
+            token.uuid,
+            token.superuser,
+            request.certificate_type,
+            &request.principals,
+            request.extensions.as_deref(),
+        )
+        .await?;
+    let Some(grant) = grant else {
+        warn!(
+            "token=`{}` not allowed to sign principals=`{:?}` with ssh ca=`{}`",
+            token.uuid, request.principals, name
+        );
+        return Err(ResponseError::Unauthorized());
+    };
+
+    let ttl = request.ttl.unwrap_or(grant.max_ttl_seconds);
+    if !(1..=grant.max_ttl_seconds).contains(&ttl) {
+        return Err(ResponseError::InvalidCertificateRequest(format!(
+            "ttl must be between 1 and {} seconds",
+            grant.max_ttl_seconds
+        )));
+    }
+
+    let secret = Secret::find(&state.database, authority.secret).await?;
+    let ca_key = SshCertificateAuthority::load_private_key(&secret)?;
+    let signed = grant.sign(
+        &ca_key,
+        &public_key,
+        request.certificate_type,
+        format!("vssv-token-{}", token.uuid),
+        &request.principals,
+        ttl,
+    )?;
+
+    let _ = AuditLogEntry::log_action_with_details(
+        &state.database,
+        client_addr.ip,
+        AuditLogAction::SshCertificateSign,
+        token.uuid,
+        authority.secret,
+        json!({
+            "fingerprint": signed.fingerprint,
+            "principals": request.principals,
+            "certificate_type": request.certificate_type,
+            "serial": signed.serial,
+        }),
+    )
+    .await?;
+
+    Ok(Json(json!({
+        "certificate": signed.certificate,
+        "serial": signed.serial,
+        "fingerprint": signed.fingerprint,
+        "principals": request.principals,
+        "extensions": grant.extensions,
+        "expires_at": signed.not_after,
+    })))
+}
+
+/// Endpoint that returns the SSH CA's public key in the OpenSSH format, for use
+/// in `TrustedUserCAKeys` or `@cert-authority` lines. This doesn't require a
+/// token.
+#[axum::debug_handler]
+pub async fn get_ssh_public_key(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+) -> Result<impl IntoResponse, ResponseError> {
+    let Some(authority) = SshCertificateAuthority::try_find_by_name(&state.database, &name).await?
+    else {
+        return Err(ResponseError::NotFoundError());
+    };
+
+    let secret = Secret::find(&state.database, authority.secret).await?;
+    let ca_key = SshCertificateAuthority::load_private_key(&secret)?;
+    let public_key = ca_key
+        .public_key()
+        .to_openssh()
+        .map_err(anyhow::Error::from)?;
+
+    Ok((
+        [(header::CONTENT_TYPE, "text/plain; charset=utf-8")],
+        public_key + "\n",
+    ))
+}
@@ -0,0 +1,289 @@
+use axum::{
+    Json, Router,
+    extract::{Path, State},
+    response::IntoResponse,
+    routing::{get, post},
+};
+use axum_extra::extract::Query;
+use serde::Deserialize;
+use serde_json::json;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::{
+    components::app_state::AppState,
+    entities::{
+        AuditLogAction, AuditLogEntry, DailyTokenUsage, ExtractClientAddr, ExtractValidToken,
+        MAX_USAGE_DAYS, SecretGrant, SecretSummary, StaleTokenReport, Token, TokenUsage,
+        TransitKeySummary,
+    },
+    errors::ResponseError,
+};
+
+pub fn build() -> Router<AppState> {
+    Router::new()
+        .route("/token/child", post(post_token_child))
+        .route("/token/self", get(get_token_self))
+        .route("/token/{uuid}/suspend", post(post_token_suspend))
+        .route("/token/{uuid}/unsuspend", post(post_token_unsuspend))
+        .route("/token/{uuid}/usage", get(get_token_usage))
+        .route("/tokens/stale", get(get_tokens_stale))
+        .route("/tokens/usage", get(list_tokens_usage))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ChildTokenRequest {
+    ttl: i64,
+    notes: Option<String>,
+    #[serde(default)]
+    permissions: Vec<SecretGrant>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SuspendRequest {
+    reason: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UsageQuery {
+    #[serde(default = "default_usage_days")]
+    days: i32,
+}
+
+fn default_usage_days() -> i32 {
+    30
+}
+
+#[derive(Debug, Deserialize)]
+pub struct StaleQuery {
+    #[serde(default = "default_idle_days")]
+    idle_days: i32,
+    #[serde(default = "default_expiring_within_days")]
+    expiring_within_days: i32,
+}
+
+fn default_idle_days() -> i32 {
+    90
+}
+
+fn default_expiring_within_days() -> i32 {
+    14
+}
+
+/// Endpoint that allows a token to inspect itself. It returns the token's
+/// metadata, its roles and flags, and all secrets and transit keys it can
+/// access, so clients can check their setup before trying to use them. Like
+/// every other endpoint, this still returns a plain 401 for invalid or expired
+/// tokens.
+#[axum::debug_handler]
+pub async fn get_token_self(
+    State(state): State<AppState>,
+    ExtractValidToken(token): ExtractValidToken,
+) -> Result<impl IntoResponse, ResponseError> {
+    let roles = token.role_names(&state.database).await?;
+    let secrets =
+        SecretSummary::list_accessible(&state.database, token.uuid, token.superuser, &[]).await?;
+    let transit_keys =
+        TransitKeySummary::list_accessible(&state.database, token.uuid, token.superuser).await?;
+
+    Ok(Json(json!({
+        "uuid": token.uuid,
+        "parent": token.parent,
+        "notes": token.notes,
+        "expires_at": token.expires_at,
+        "superuser": token.superuser,
+        "can_approve": token.can_approve,
+        "break_glass": token.break_glass,
+        "require_signed_requests": token.require_signed_requests,
+        "roles": roles,
+        "secrets": secrets,
+        "transit_keys": transit_keys,
+    })))
+}
+
+/// Endpoint that creates a short-lived child token with a subset of the calling
+/// token's permissions on secrets. Child tokens never have roles or any other
+/// permissions, and they can't outlive their parent. If the parent loses access
+/// to a secret later, or gets deleted, so do all its children.
+#[axum::debug_handler]
+pub async fn post_token_child(
+    State(state): State<AppState>,
+    ExtractValidToken(token): ExtractValidToken,
+    Json(request): Json<ChildTokenRequest>,
+) -> Result<impl IntoResponse, ResponseError> {
+    let max_ttl = token.max_child_ttl();
+    if !(1..=max_ttl).contains(&request.ttl) {
+        return Err(ResponseError::InvalidTtl(max_ttl.max(0)));
+    }
+
+    for grant in &request.permissions {
+        let allowed = (!grant.can_read
+            || token.can_read_secret(&state.database, grant.secret).await?)
+            && (!grant.can_write
+                || token
+                    .can_write_secret(&state.database, grant.secret)
+                    .await?);
+        if !allowed {
+            warn!(
+                "token=`{}` not allowed to pass permissions on secret=`{}` to a child",
+                token.uuid, grant.secret
+            );
+            return Err(ResponseError::Unauthorized());
+        }
+    }
+
+    let mut tx = state.database.begin().await?;
+    let (child, child_token) = token
+        .create_child(
+            &mut tx,
+            request.ttl,
+            request.notes.as_deref(),
+            &request.permissions,
+        )
+        .await?;
+    tx.commit().await?;
+
+    info!(
+        "token=`{}` created child token=`{}`",
+        token.uuid, child.uuid
+    );
+    Ok(Json(json!({
+        "uuid": child.uuid,
+        "token": child_token,
+        "parent": child.parent,
+        "expires_at": child.expires_at,
+    })))
+}
+
+/// Endpoint that lists the usage of all tokens, including the number of
+/// requests in the last `days` days (30 by default). Tokens that were never
+/// used, or haven't been used for the longest time, come first. This requires a
+/// superuser token.
+#[axum::debug_handler]
+pub async fn list_tokens_usage(
+    State(state): State<AppState>,
+    Query(query): Query<UsageQuery>,
+    ExtractValidToken(token): ExtractValidToken,
+) -> Result<Json<Vec<TokenUsage>>, ResponseError> {
+    if !token.superuser {
+        warn!("token=`{}` not allowed to list token usage", token.uuid);
+        return Err(ResponseError::Unauthorized());
+    }
+
+    let days = query.days.clamp(1, MAX_USAGE_DAYS);
+    Ok(Json(TokenUsage::list(&state.database, days).await?))
+}
+
+/// Endpoint that returns a single token's usage, including its number of
+/// requests per day in the last `days` days (30 by default). This requires a
+/// superuser token.
+#[axum::debug_handler]
+pub async fn get_token_usage(
+    State(state): State<AppState>,
+    Path(uuid): Path<Uuid>,
+    Query(query): Query<UsageQuery>,
+    ExtractValidToken(token): ExtractValidToken,
+) -> Result<impl IntoResponse, ResponseError> {
+    if !token.superuser {
+        warn!("token=`{}` not allowed to read token usage", token.uuid);
+        return Err(ResponseError::Unauthorized());
+    }
+
+    let days = query.days.clamp(1, MAX_USAGE_DAYS);
+    let Some(usage) = TokenUsage::try_find(&state.database, uuid, days).await? else {
+        return Err(ResponseError::NotFoundError());
+    };
+    let daily = DailyTokenUsage::find_for_token(&state.database, uuid, days).await?;
+
+    Ok(Json(json!({ "usage": usage, "daily": daily })))
+}
+
+/// Endpoint that reports tokens that deserve a closer look: tokens that were
+/// never used, that haven't been used in `idle_days` days (90 by default), that
+/// expire within `expiring_within_days` days (14 by default), that have been
+/// used after they expired, and all superuser tokens. This requires a superuser
+/// token.
+#[axum::debug_handler]
+pub async fn get_tokens_stale(
+    State(state): State<AppState>,
+    Query(query): Query<StaleQuery>,
+    ExtractValidToken(token): ExtractValidToken,
+) -> Result<Json<StaleTokenReport>, ResponseError> {
+    if !token.superuser {
+        warn!(
+            "token=`{}` not allowed to read the stale token report",
+            token.uuid
+        );
+        return Err(ResponseError::Unauthorized());
+    }
+
+    Ok(Json(
+        StaleTokenReport::build(&state.database, query.idle_days, query.expiring_within_days)
+            .await?,
+    ))
+}
+
+/// Endpoint that suspends a token. Suspended tokens and their children are
+/// rejected, but keep all their permissions, so they can be investigated and
+/// unsuspended later. This requires a superuser token.
+#[axum::debug_handler]
+pub async fn post_token_suspend(
+    State(state): State<AppState>,
+    Path(uuid): Path<Uuid>,
+    ExtractClientAddr(client_addr): ExtractClientAddr,
+    ExtractValidToken(token): ExtractValidToken,
+    Json(request): Json<SuspendRequest>,
+) -> Result<impl IntoResponse, ResponseError> {
+    if !token.superuser {
+        warn!("token=`{}` not allowed to suspend tokens", token.uuid);
+        return Err(ResponseError::Unauthorized());
+    }
+
+    let mut tx = state.database.begin().await?;
+    if !Token::suspend(&mut *tx, uuid, &request.reason).await? {
+        return Err(ResponseError::NotFoundError());
+    }
+    let _ = AuditLogEntry::log_token_action(
+        &mut *tx,
+        client_addr.ip,
+        AuditLogAction::TokenSuspend,
+        token.uuid,
+        json!({ "token": uuid, "reason": request.reason }),
+    )
+    .await?;
+    tx.commit().await?;
+
+    info!("token=`{}` suspended token=`{}`", token.uuid, uuid);
+    Ok(Json(json!({ "uuid": uuid, "suspended": true })))
+}
+
+/// Endpoint that lifts a token's suspension. This requires a superuser token.
+#[axum::debug_handler]
+pub async fn post_token_unsuspend(
+    State(state): State<AppState>,
+    Path(uuid): Path<Uuid>,
+    ExtractClientAddr(client_addr): ExtractClientAddr,
+    ExtractValidToken(token): ExtractValidToken,
+) -> Result<impl IntoResponse, ResponseError> {
+    if !token.superuser {
+        warn!("token=`{}` not allowed to unsuspend tokens", token.uuid);
+        return Err(ResponseError::Unauthorized());
+    }
+
+    let mut tx = state.database.begin().await?;
+    if !Token::unsuspend(&mut *tx, uuid).await? {
+        return Err(ResponseError::NotFoundError());
+    }
+    let _ = AuditLogEntry::log_token_action(
+        &mut *tx,
+        client_addr.ip,
+        AuditLogAction::TokenUnsuspend,
+        token.uuid,
+        json!({ "token": uuid }),
+    )
+    .await?;
+    tx.commit().await?;
+
+    info!("token=`{}` unsuspended token=`{}`", token.uuid, uuid);
+    Ok(Json(json!({ "uuid": uuid, "suspended": false })))
+}
@@ -0,0 +1,226 @@
+use axum::{
+    Json, Router,
+    extract::{Path, State},
+    response::IntoResponse,
+    routing::post,
+};
+use base64::{Engine, engine::general_purpose::STANDARD as BASE64};
+use serde::Deserialize;
+use serde_json::json;
+use tracing::warn;
+
+use crate::{
+    components::app_state::AppState,
+    entities::{
+        AuditLogAction, AuditLogEntry, ClientAddr, ExtractClientAddr, ExtractValidToken, Token,
+        TransitKey, decode_base64, parse_versioned,
+    },
+    errors::ResponseError,
+};
+
+pub fn build() -> Router<AppState> {
+    Router::new()
+        .route("/transit/{name}/encrypt", post(post_transit_encrypt))
+        .route("/transit/{name}/decrypt", post(post_transit_decrypt))
+        .route("/transit/{name}/rewrap", post(post_transit_rewrap))
+        .route("/transit/{name}/sign", post(post_transit_sign))
+        .route("/transit/{name}/verify", post(post_transit_verify))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct EncryptRequest {
+    plaintext: String,
+}
+
+/// Endpoint that encrypts a base64-encoded plaintext with the latest version of
+/// an AES-256-GCM transit key. Neither the plaintext nor the ciphertext are
+/// stored.
+#[axum::debug_handler]
+pub async fn post_transit_encrypt(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    ExtractClientAddr(client_addr): ExtractClientAddr,
+    ExtractValidToken(token): ExtractValidToken,
+    Json(request): Json<EncryptRequest>,
+) -> Result<impl IntoResponse, ResponseError> {
+    let plaintext = decode_base64("plaintext", &request.plaintext)?;
+    let key = use_transit_key(
+        &state,
+        &name,
+        None,
+        AuditLogAction::TransitEncrypt,
+        &client_addr,
+        &token,
+    )
+    .await?;
+
+    Ok(Json(json!({
+        "ciphertext": key.encrypt(&plaintext)?,
+        "key_version": key.version,
+    })))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DecryptRequest {
+    ciphertext: String,
+}
+
+/// Endpoint that decrypts a ciphertext created by [post_transit_encrypt]. The
+/// key version is taken from the ciphertext, so this keeps working after the
+/// key has been rotated. The plaintext is returned base64-encoded.
+#[axum::debug_handler]
+pub async fn post_transit_decrypt(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    ExtractClientAddr(client_addr): ExtractClientAddr,
+    ExtractValidToken(token): ExtractValidToken,
+    Json(request): Json<DecryptRequest>,
+) -> Result<impl IntoResponse, ResponseError> {
+    let (version, payload) = parse_versioned(&request.ciphertext)?;
+    let key = use_transit_key(
+        &state,
+        &name,
+        Some(version),
+        AuditLogAction::TransitDecrypt,
+        &client_addr,
+        &token,
+    )
+    .await?;
+
+    Ok(Json(json!({
+        "plaintext": BASE64.encode(key.decrypt(&payload)?),
+    })))
+}
+
+/// Endpoint that re-encrypts a ciphertext with the latest key version, without
+/// ever returning the plaintext. This should be used after rotating a key.
+#[axum::debug_handler]
+pub async fn post_transit_rewrap(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    ExtractClientAddr(client_addr): ExtractClientAddr,
+    ExtractValidToken(token): ExtractValidToken,
+    Json(request): Json<DecryptRequest>,
+) -> Result<impl IntoResponse, ResponseError> {
+    let (version, payload) = parse_versioned(&request.ciphertext)?;
+    let old_key = use_transit_key(
+        &state,
+        &name,
+        Some(version),
+        AuditLogAction::TransitRewrap,
+        &client_addr,
+        &token,
+    )
+    .await?;
+    let plaintext = old_key.decrypt(&payload)?;
+
+    let Some(new_key) = TransitKey::try_find(&state.database, &name, None).await? else {
+        return Err(ResponseError::NotFoundError());
+    };
+
+    Ok(Json(json!({
+        "ciphertext": new_key.encrypt(&plaintext)?,
+        "key_version": new_key.version,
+    })))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SignRequest {
+    input: String,
+}
+
+/// Endpoint that signs a base64-encoded input with the latest version of an
+/// Ed25519 transit key.
+#[axum::debug_handler]
+pub async fn post_transit_sign(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    ExtractClientAddr(client_addr): ExtractClientAddr,
+    ExtractValidToken(token): ExtractValidToken,
+    Json(request): Json<SignRequest>,
+) -> Result<impl IntoResponse, ResponseError> {
+    let input = decode_base64("input", &request.input)?;
+    let key = use_transit_key(
+        &state,
+        &name,
+        None,
+        AuditLogAction::TransitSign,
+        &client_addr,
+        &token,
+    )
+    .await?;
+
+    Ok(Json(json!({
+        "signature": key.sign(&input)?,
+        "key_version": key.version,
+    })))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct VerifyRequest {
+    input: String,
+    signature: String,
+}
+
+/// Endpoint that verifies a signature created by [post_transit_sign]. A
+/// signature that doesn't match results in `"valid": false`, not an error.
+#[axum::debug_handler]
+pub async fn post_transit_verify(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    ExtractClientAddr(client_addr): ExtractClientAddr,
+    ExtractValidToken(token): ExtractValidToken,
+    Json(request): Json<VerifyRequest>,
+) -> Result<impl IntoResponse, ResponseError> {
+    let input = decode_base64("input", &request.input)?;
+    let (version, signature) = parse_versioned(&request.signature)?;
+    let key = use_transit_key(
+        &state,
+        &name,
+        Some(version),
+        AuditLogAction::TransitVerify,
+        &client_addr,
+        &token,
+    )
+    .await?;
+
+    Ok(Json(json!({
+        "valid": key.verify(&input, &signature)?,
+    })))
+}
+
+/// Loads a transit key for a given action. This checks the token's permissions
+/// first, then loads the requested key version, and stores the action in the
+/// audit log, so the caller only has to do the actual operation.
+async fn use_transit_key(
+    state: &AppState,
+    name: &str,
+    version: Option<i32>,
+    action: AuditLogAction,
+    client_addr: &ClientAddr,
+    token: &Token,
+) -> Result<TransitKey, ResponseError> {
+    let permissions = token.transit_permissions(&state.database, name).await?;
+    if !permissions.allows(&action) {
+        warn!(
+            "token=`{}` not allowed to {:?} with transit key=`{}`",
+            token.uuid, action, name
+        );
+        return Err(ResponseError::Unauthorized());
+    }
+
+    let Some(key) = TransitKey::try_find(&state.database, name, version).await? else {
+        return Err(ResponseError::NotFoundError());
+    };
+
+    let _ = AuditLogEntry::log_transit_action(
+        &state.database,
+        client_addr.ip,
+        action,
+        token.uuid,
+        key.uuid,
+    )
+    .await?;
+
+    Ok(key)
+}